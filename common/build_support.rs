@@ -0,0 +1,57 @@
+use std::path::Path;
+use std::process::Command;
+
+// The skia-bindings versions whose GrContextOptions::PersistentCache the shim in
+// persistent_cache.cpp has been checked against (m100 for 0.50, m102 for 0.52)
+const CHECKED_BINDINGS: &[&str] = &["0.50.0", "0.52.0"];
+
+// The version Cargo resolved for each of the named packages, from `cargo metadata` (which only
+// reads what's already been resolved and downloaded for this build)
+fn resolved_versions(names:&[&str]) -> Result<Vec<String>, String> {
+    let cargo = std::env::var_os("CARGO").ok_or("CARGO isn't set")?;
+    let target = std::env::var("TARGET").map_err(|e| e.to_string())?;
+    let output = Command::new(cargo)
+        .args(["metadata", "--format-version", "1", "--offline", "--filter-platform", &target])
+        .current_dir(std::env::var_os("CARGO_MANIFEST_DIR").ok_or("CARGO_MANIFEST_DIR isn't set")?)
+        .output()
+        .map_err(|e| format!("Could not run cargo metadata: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("cargo metadata failed: {}", String::from_utf8_lossy(&output.stderr).trim()))
+    }
+
+    let metadata:serde_json::Value = serde_json::from_slice(&output.stdout).map_err(|e| e.to_string())?;
+    let packages = metadata["packages"].as_array().ok_or("cargo metadata listed no packages")?;
+    names.iter().map(|name| {
+        packages.iter()
+            .find(|package| package["name"] == *name)
+            .and_then(|package| package["version"].as_str())
+            .map(String::from)
+            .ok_or_else(|| format!("{} isn't a dependency", name))
+    }).collect()
+}
+
+// Pass the skia-safe version on to the code as SKIA_SAFE_VERSION (the shader caches are keyed by
+// it, since Skia generates different shaders from one version to the next), and compile the
+// PersistentCache shim, refusing to if skia-bindings has moved on to a version it hasn't been
+// checked against
+pub fn build_persistent_cache() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let (safe, bindings) = match resolved_versions(&["skia-safe", "skia-bindings"]).as_deref() {
+        Ok([safe, bindings]) => (safe.clone(), bindings.clone()),
+        Ok(_) => unreachable!(),
+        Err(msg) => panic!("Could not find the Skia version: {}", msg),
+    };
+    if !CHECKED_BINDINGS.contains(&bindings.as_str()) {
+        panic!("persistent_cache.cpp hasn't been checked against skia-bindings {} (only {})", bindings, CHECKED_BINDINGS.join(", "));
+    }
+    println!("cargo:rustc-env=SKIA_SAFE_VERSION={}", safe);
+
+    let shim = Path::new("../common/persistent_cache.cpp");
+    println!("cargo:rerun-if-changed={}", shim.display());
+    cc::Build::new()
+        .cpp(true)
+        .flag_if_supported("-std=c++17")
+        .flag_if_supported("-fno-exceptions")
+        .file(shim)
+        .compile("persistent_cache");
+}
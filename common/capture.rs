@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use skia_safe::{AlphaType, Canvas, ColorType, Data, EncodedImageFormat, Image, ImageInfo};
+
+// Copy the canvas's current contents back from the GPU into a raster image. Skia takes care of
+// flipping surfaces with a bottom-left origin (like GL's default framebuffer), so the rows
+// always come back top-to-bottom.
+pub fn read_canvas(canvas:&mut Canvas) -> Result<Image, String> {
+    let size = canvas.base_layer_size();
+    let info = ImageInfo::new(size, ColorType::RGBA8888, AlphaType::Premul, None);
+    let row_bytes = info.min_row_bytes();
+    let mut pixels = vec![0u8; row_bytes * size.height as usize];
+
+    match canvas.read_pixels(&info, &mut pixels, row_bytes, (0, 0)) {
+        true => Image::from_raster_data(&info, Data::new_copy(&pixels), row_bytes)
+            .ok_or_else(|| "Could not create image from framebuffer".to_string()),
+        false => Err("Could not read pixels from framebuffer".to_string())
+    }
+}
+
+pub fn encode(image:&Image, format:EncodedImageFormat, quality:u32) -> Result<Data, String> {
+    image.encode_to_data_with_quality(format, quality as _)
+        .ok_or_else(|| format!("Could not encode frame as {:?}", format))
+}
+
+pub fn extension(format:EncodedImageFormat) -> &'static str {
+    match format {
+        EncodedImageFormat::JPEG => "jpg",
+        EncodedImageFormat::WEBP => "webp",
+        _ => "png"
+    }
+}
+
+pub fn timestamp() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
+}
+
+// Write the data to the working directory as `<prefix>-<milliseconds since epoch>.<ext>`
+pub fn save_timestamped(data:&Data, prefix:&str, ext:&str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(format!("{}-{}.{}", prefix, timestamp(), ext));
+    std::fs::write(&path, data.as_bytes())
+        .map(|_| path.clone())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
@@ -0,0 +1,230 @@
+use std::collections::VecDeque;
+use skia_safe::{
+    gpu::{Budgeted, DirectContext, SurfaceOrigin},
+    Canvas, Color4f, IRect, ImageInfo, Paint, Path, Rect, RoundOut, SamplingOptions, Surface,
+};
+
+// how many frames a redrawn region stays highlighted for in DamageMode::Flash
+const FLASH_FRAMES: usize = 12;
+
+// how many frames' damage to remember for windows whose buffers come back with older contents
+const MAX_BUFFER_AGE: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DamageMode {
+    Full,  // ← redraw every frame in full, straight to the window
+    Clip,  // ← only redraw the parts of the frame the scene reports as changed
+    Flash, // ← also highlight those parts for a few frames
+}
+
+impl std::str::FromStr for DamageMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" | "full" => Ok(DamageMode::Full),
+            "" | "1" | "on" | "clip" => Ok(DamageMode::Clip),
+            "flash" => Ok(DamageMode::Flash),
+            _ => Err(format!("Unknown damage mode: {:?}", s))
+        }
+    }
+}
+
+// The parts of a frame that changed since the one before it, in the scene's own coordinates
+#[derive(Clone, Debug, Default)]
+pub struct Damage {
+    rects: Vec<Rect>,
+    full: bool,
+}
+
+impl Damage {
+    pub fn full() -> Self {
+        Damage{ rects: vec![], full: true }
+    }
+
+    pub fn add(&mut self, rect:Rect){
+        if !rect.is_empty() {
+            // widened to whole pixels (and then some) so antialiased edges get redrawn too
+            let rect:IRect = rect.round_out();
+            self.rects.push(Rect::from_irect(rect).with_outset((1.0, 1.0)));
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects
+    }
+
+    fn clip(&self, canvas:&mut Canvas){
+        if !self.full {
+            let mut path = Path::new();
+            for rect in &self.rects {
+                path.add_rect(rect, None);
+            }
+            canvas.clip_path(&path, None, false);
+        }
+    }
+}
+
+// With partial redraw on, keeps the last frame in an offscreen surface so the next one only has
+// to redraw its damaged parts (and so the OS's redraws can be answered without drawing anything),
+// and then copies it to the window. In DamageMode::Full there's no such surface, and every frame
+// is drawn straight to the window. Partial presentation only tells the compositor which
+// parts changed, so the window's own buffers still need every pixel. EGL's buffer age says which
+// frame a recycled buffer still holds, so only what's changed since then is copied; skulpin and
+// Metal won't say, so there the whole frame is copied every time.
+pub struct DamageTracker {
+    mode: DamageMode,
+    retained: Option<Surface>,
+    invalid: bool,
+    drawing: bool,
+    flashes: Vec<(Rect, usize)>,
+    expired: Vec<Rect>,
+    history: VecDeque<Damage>, // ← what changed in the window in each of the last few frames
+}
+
+impl DamageTracker {
+    pub fn new(mode:DamageMode) -> Self {
+        DamageTracker{ mode, retained: None, invalid: true, drawing: false, flashes: vec![], expired: vec![], history: VecDeque::new() }
+    }
+
+    // Redraw the next frame in full, for changes the scene doesn't report (like toggling the HUD)
+    pub fn invalidate(&mut self){
+        self.invalid = true;
+    }
+
+    // Forget the retained frame when the context it belonged to is gone
+    pub fn abandon(&mut self){
+        self.retained = None;
+        self.drawing = false;
+        self.history.clear();
+    }
+
+    // whether the last frame is there to be shown again in a window of `size` pixels
+    pub fn has_frame(&self, (width, height):(i32, i32)) -> bool {
+        !self.drawing && self.retained.as_ref().map_or(false, |surface| (surface.width(), surface.height()) == (width, height))
+    }
+
+    // Copy the last frame to the window's canvas again, unchanged
+    pub fn repeat(&mut self, canvas:&mut Canvas){
+        if let Some(surface) = self.retained.as_mut() {
+            canvas.save();
+            canvas.reset_matrix();
+            surface.draw(canvas, (0, 0), SamplingOptions::default(), None);
+            canvas.restore();
+        }
+        self.remember(Damage::default());
+    }
+
+    // Start drawing a frame of `size` pixels over the previous one, clipped to `damage`. The damage
+    // is widened to the whole frame if there's nothing to draw over. `scale` maps the scene's
+    // coordinates to pixels.
+    pub fn begin(&mut self, context:&mut DirectContext, (width, height):(i32, i32), scale:f32, damage:&mut Damage){
+        if self.mode == DamageMode::Full {
+            *damage = Damage::full();
+            return
+        }
+
+        let stale = self.retained.as_ref().map_or(true, |surface| (surface.width(), surface.height()) != (width, height));
+        if stale {
+            let info = ImageInfo::new_n32_premul((width, height), None);
+            self.retained = Surface::new_render_target(context, Budgeted::Yes, &info, None, SurfaceOrigin::TopLeft, None, false);
+        }
+        if stale || std::mem::take(&mut self.invalid) {
+            *damage = Damage::full();
+        }
+
+        match self.retained.as_mut() {
+            Some(surface) => {
+                let canvas = surface.canvas();
+                canvas.save();
+                canvas.scale((scale, scale));
+                damage.clip(canvas);
+                self.drawing = true;
+            }
+            None => *damage = Damage::full() // ← draw straight to the window instead
+        }
+    }
+
+    // where the frame gets drawn between `begin` and `finish`
+    pub fn canvas(&mut self) -> Option<&mut Canvas> {
+        match self.drawing {
+            true => self.retained.as_mut().map(|surface| surface.canvas()),
+            false => None
+        }
+    }
+
+    // Copy the frame to the window's canvas (flashing the damage on top if asked to) and return
+    // what changed in the window, flashes included. `buffer_age` is how many frames ago the
+    // window's buffer was last drawn into, or 0 if there's no telling what it holds.
+    pub fn finish(&mut self, canvas:&mut Canvas, damage:&Damage, buffer_age:usize) -> Damage {
+        let mut changed = damage.clone();
+        let mut flashes = vec![];
+        if self.mode == DamageMode::Flash {
+            let added = match damage.is_full() {
+                true => canvas.local_clip_bounds().into_iter().collect(),
+                false => damage.rects().to_vec()
+            };
+            self.flashes.extend(added.into_iter().map(|rect| (rect, FLASH_FRAMES)));
+
+            // the last frame's faded flashes have to be drawn over too
+            changed.rects.append(&mut self.expired);
+            for (rect, frames) in self.flashes.iter_mut() {
+                flashes.push((*rect, *frames));
+                changed.rects.push(*rect);
+                *frames -= 1;
+            }
+            let (faded, flashes):(Vec<_>, Vec<_>) = self.flashes.drain(..).partition(|(_, frames)| *frames == 0);
+            self.flashes = flashes;
+            self.expired = faded.into_iter().map(|(rect, _)| rect).collect();
+        }
+
+        if std::mem::take(&mut self.drawing) {
+            let missing = self.missing(&changed, buffer_age);
+            if let Some(surface) = self.retained.as_mut() {
+                surface.canvas().restore();
+                canvas.save();
+                if let Some(missing) = missing {
+                    missing.clip(canvas);
+                }
+                canvas.reset_matrix();
+                surface.draw(canvas, (0, 0), SamplingOptions::default(), None);
+                canvas.restore();
+            }
+        }
+
+        let mut paint = Paint::new(Color4f::new(1.0, 0.0, 0.4, 1.0), None);
+        for (rect, frames) in flashes {
+            paint.set_alpha_f(0.5 * frames as f32 / FLASH_FRAMES as f32);
+            canvas.draw_rect(rect, &paint);
+        }
+
+        self.remember(changed.clone());
+        changed
+    }
+
+    // What a window buffer last drawn into `age` frames ago lacks, or None if the whole frame
+    // has to be copied
+    fn missing(&self, changed:&Damage, age:usize) -> Option<Damage> {
+        if age == 0 || age > self.history.len() + 1 || changed.is_full() {
+            return None
+        }
+        let mut missing = changed.clone();
+        for damage in self.history.iter().rev().take(age - 1) {
+            if damage.is_full() {
+                return None
+            }
+            missing.rects.extend_from_slice(damage.rects());
+        }
+        Some(missing)
+    }
+
+    fn remember(&mut self, damage:Damage){
+        if self.history.len() == MAX_BUFFER_AGE {
+            self.history.pop_front();
+        }
+        self.history.push_back(damage);
+    }
+}
@@ -0,0 +1,20 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+// Read a setting from the environment, or None if it isn't set. A value that doesn't parse ends
+// the process with a message instead of a panic (or a run with some setting nobody asked for).
+pub fn parse<T>(name:&str) -> Option<T> where T:FromStr, T::Err:Display {
+    parse_with(name, str::parse)
+}
+
+// Like `parse`, for settings that aren't read with FromStr
+pub fn parse_with<T, E:Display>(name:&str, parse:impl FnOnce(&str) -> Result<T, E>) -> Option<T> {
+    let value = std::env::var(name).ok()?;
+    match parse(&value) {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            eprintln!("{}={:?}: {}", name, value, e);
+            std::process::exit(1);
+        }
+    }
+}
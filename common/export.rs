@@ -0,0 +1,47 @@
+use skia_safe::{pdf, svg, Canvas, Data, Rect};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Svg,
+    Pdf,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "svg" => Ok(ExportFormat::Svg),
+            "pdf" => Ok(ExportFormat::Pdf),
+            _ => Err(format!("Unknown export format: {:?}", s))
+        }
+    }
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Svg => "svg",
+            ExportFormat::Pdf => "pdf",
+        }
+    }
+}
+
+// Run `draw` against one of Skia's vector backends instead of the GPU and return the encoded
+// document. The canvas is `size` logical pixels across (which PDF treats as points), and since no
+// GPU context is involved this works whether or not the window's context is usable.
+pub fn export<F>(format:ExportFormat, (width, height):(f32, f32), draw:F) -> Data
+    where F: FnOnce(&mut Canvas)
+{
+    match format {
+        ExportFormat::Svg => {
+            let mut canvas = svg::Canvas::new(Rect::from_wh(width, height), None);
+            draw(&mut canvas);
+            canvas.end()
+        }
+        ExportFormat::Pdf => {
+            let mut page = pdf::new_document(None).begin_page((width, height), None);
+            draw(page.canvas());
+            page.end_page().close()
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use skia_safe::{
+    gpu::{Budgeted, DirectContext, SurfaceOrigin},
+    Canvas, Color, Color4f, FilterMode, ImageInfo, Matrix, MipmapMode, Paint, PaintStyle, Point,
+    SamplingOptions, Surface,
+};
+
+// How a fixed-size canvas is placed in a window of some other size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FitMode {
+    Contain, // ← as large as fits without cropping, letterboxed along the other axis
+    Cover,   // ← as small as fills the window, cropped along the other axis
+    Fill,    // ← stretched to the window's shape
+    None,    // ← at its own size in logical points, centered
+    Pixel,   // ← one canvas pixel per screen pixel, centered
+}
+
+impl std::str::FromStr for FitMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "contain" => Ok(FitMode::Contain),
+            "cover" => Ok(FitMode::Cover),
+            "fill" | "stretch" => Ok(FitMode::Fill),
+            "none" | "center" => Ok(FitMode::None),
+            "pixel" | "1:1" => Ok(FitMode::Pixel),
+            _ => Err(format!("Unknown fit mode: {:?}", s))
+        }
+    }
+}
+
+impl FitMode {
+    pub fn next(&self) -> Self {
+        match self {
+            FitMode::Contain => FitMode::Cover,
+            FitMode::Cover => FitMode::Fill,
+            FitMode::Fill => FitMode::None,
+            FitMode::None => FitMode::Pixel,
+            FitMode::Pixel => FitMode::Contain,
+        }
+    }
+}
+
+// A canvas of a fixed pixel size that gets drawn offscreen and then scaled into the window, the
+// way a skia-canvas Canvas would be shown in a window that can be resized
+pub struct CanvasFit {
+    size: (i32, i32),
+    mode: FitMode,
+    letterbox: Color,
+    aspect_lock: bool,
+    window: (u32, u32), // ← the window size last seen by `lock_aspect`
+    target: Option<Surface>,
+}
+
+impl CanvasFit {
+    pub fn new(size:(i32, i32), mode:FitMode, letterbox:Color, aspect_lock:bool) -> Self {
+        CanvasFit{ size, mode, letterbox, aspect_lock, window: (0, 0), target: None }
+    }
+
+    // CANVAS_SIZE=<width>x<height> turns the fixed canvas on, FIT picks the mode (contain by
+    // default), LETTERBOX sets the color around it as rrggbb, and ASPECT_LOCK keeps the window's
+    // shape in line with the canvas's as it's resized
+    pub fn from_env() -> Option<Self> {
+        let size = crate::env::parse_with("CANVAS_SIZE", parse_size)?;
+        let mode = crate::env::parse("FIT").unwrap_or(FitMode::Contain);
+        let letterbox = crate::env::parse_with("LETTERBOX", parse_color).unwrap_or(Color::BLACK);
+        Some(CanvasFit::new(size, mode, letterbox, std::env::var("ASPECT_LOCK").is_ok()))
+    }
+
+    pub fn size(&self) -> (i32, i32) {
+        self.size
+    }
+
+    pub fn cycle_mode(&mut self) -> FitMode {
+        self.mode = self.mode.next();
+        self.mode
+    }
+
+    // whether the canvas holds a frame that can be shown again
+    pub fn has_frame(&self) -> bool {
+        self.target.is_some()
+    }
+
+    // Forget the canvas's contents when the context they belonged to is gone
+    pub fn abandon(&mut self){
+        self.target = None;
+    }
+
+    // where the canvas gets drawn, created the first time it's needed
+    pub fn surface(&mut self, context:&mut DirectContext) -> Option<&mut Surface> {
+        if self.target.is_none() {
+            let info = ImageInfo::new_n32_premul(self.size, None);
+            self.target = Surface::new_render_target(context, Budgeted::Yes, &info, None, SurfaceOrigin::TopLeft, None, false);
+        }
+        self.target.as_mut()
+    }
+
+    // Maps the canvas's pixels to those of a `window` of the given pixel size whose scale factor is `sf`
+    pub fn matrix(&self, (width, height):(f32, f32), sf:f32) -> Matrix {
+        let (cw, ch) = (self.size.0 as f32, self.size.1 as f32);
+        let (sx, sy) = match self.mode {
+            FitMode::Contain => { let s = (width / cw).min(height / ch); (s, s) }
+            FitMode::Cover => { let s = (width / cw).max(height / ch); (s, s) }
+            FitMode::Fill => (width / cw, height / ch),
+            FitMode::None => (sf, sf),
+            FitMode::Pixel => (1.0, 1.0),
+        };
+        // whole pixels, so a 1:1 canvas isn't resampled across a seam
+        let offset = (((width - cw * sx) / 2.0).round(), ((height - ch * sy) / 2.0).round());
+        let mut matrix = Matrix::new_scale((sx, sy));
+        matrix.post_translate(offset);
+        matrix
+    }
+
+    // Where a point in the window (in pixels) lands on the canvas, if it's over it at all
+    pub fn to_canvas(&self, point:Point, window:(f32, f32), sf:f32) -> Option<Point> {
+        let point = self.matrix(window, sf).invert()?.map_point(point);
+        let (cw, ch) = (self.size.0 as f32, self.size.1 as f32);
+        (point.x >= 0.0 && point.y >= 0.0 && point.x < cw && point.y < ch).then(|| point)
+    }
+
+    // Draw the canvas into the window's canvas, filling whatever it leaves uncovered
+    pub fn present(&mut self, canvas:&mut Canvas, window:(f32, f32), sf:f32){
+        let matrix = self.matrix(window, sf);
+        let filter = match self.mode {
+            FitMode::Pixel => FilterMode::Nearest,
+            _ => FilterMode::Linear,
+        };
+        canvas.save();
+        canvas.reset_matrix();
+        canvas.clear(self.letterbox);
+        canvas.concat(&matrix);
+        if let Some(target) = self.target.as_mut() {
+            target.draw(canvas, (0, 0), SamplingOptions::new(filter, MipmapMode::None), None);
+        }
+        canvas.restore();
+    }
+
+    // The size a window that was just resized to `size` should be changed to so it has the
+    // canvas's shape, if aspect locking is on and it's more than a pixel off. Whichever side was
+    // dragged further keeps its length.
+    pub fn lock_aspect(&mut self, (width, height):(u32, u32)) -> Option<(u32, u32)> {
+        let (last_width, last_height) = std::mem::replace(&mut self.window, (width, height));
+        if !self.aspect_lock || width == 0 || height == 0 {
+            return None
+        }
+        let aspect = self.size.0 as f64 / self.size.1 as f64;
+        let locked = match width.abs_diff(last_width) >= height.abs_diff(last_height) {
+            true => (width, (width as f64 / aspect).round() as u32),
+            false => ((height as f64 * aspect).round() as u32, height),
+        };
+        let off = locked.0.abs_diff(width).max(locked.1.abs_diff(height));
+        (off > 1).then(|| {
+            self.window = locked;
+            locked
+        })
+    }
+}
+
+fn parse_size(s:&str) -> Result<(i32, i32), String> {
+    let (width, height) = s.split_once('x').ok_or_else(|| format!("Canvas size should be <width>x<height>, not {:?}", s))?;
+    match (width.trim().parse(), height.trim().parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("Bad canvas size: {:?}", s))
+    }
+}
+
+fn parse_color(s:&str) -> Result<Color, String> {
+    let hex = s.trim_start_matches('#');
+    match (hex.len(), u32::from_str_radix(hex, 16)) {
+        (6, Ok(rgb)) => Ok(Color::from_rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)),
+        _ => Err(format!("Letterbox color should be rrggbb, not {:?}", s))
+    }
+}
+
+// Mark the spot on the canvas that's under the pointer
+pub fn draw_pointer(canvas:&mut Canvas, point:Point){
+    let mut paint = Paint::new(Color4f::new(1.0, 0.0, 0.4, 1.0), None);
+    paint.set_anti_alias(true);
+    paint.set_style(PaintStyle::Stroke);
+    paint.set_stroke_width(2.0);
+    canvas.draw_circle(point, 6.0, &paint);
+    canvas.draw_line((point.x - 10.0, point.y), (point.x + 10.0, point.y), &paint);
+    canvas.draw_line((point.x, point.y - 10.0), (point.x, point.y + 10.0), &paint);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fit(mode:FitMode) -> CanvasFit {
+        CanvasFit::new((200, 100), mode, Color::BLACK, true)
+    }
+
+    // where the canvas's top-left & bottom-right corners land in the window
+    fn corners(mode:FitMode, window:(f32, f32), sf:f32) -> (Point, Point) {
+        let matrix = fit(mode).matrix(window, sf);
+        (matrix.map_point((0.0, 0.0)), matrix.map_point((200.0, 100.0)))
+    }
+
+    #[test]
+    fn matrix_per_mode() {
+        let window = (400.0, 400.0);
+        assert_eq!(corners(FitMode::Contain, window, 1.5), (Point::new(0.0, 100.0), Point::new(400.0, 300.0)));
+        assert_eq!(corners(FitMode::Cover, window, 1.5), (Point::new(-200.0, 0.0), Point::new(600.0, 400.0)));
+        assert_eq!(corners(FitMode::Fill, window, 1.5), (Point::new(0.0, 0.0), Point::new(400.0, 400.0)));
+        assert_eq!(corners(FitMode::None, window, 1.5), (Point::new(50.0, 125.0), Point::new(350.0, 275.0)));
+        assert_eq!(corners(FitMode::Pixel, window, 1.5), (Point::new(100.0, 150.0), Point::new(300.0, 250.0)));
+    }
+
+    #[test]
+    fn matrix_offsets_are_whole_pixels() {
+        let (top_left, _) = corners(FitMode::Pixel, (401.0, 399.0), 1.0);
+        assert_eq!(top_left, Point::new(101.0, 150.0));
+    }
+
+    #[test]
+    fn to_canvas_inverts_the_matrix() {
+        let fit = fit(FitMode::Contain);
+        let window = (400.0, 400.0);
+        assert_eq!(fit.to_canvas(Point::new(200.0, 200.0), window, 1.0), Some(Point::new(100.0, 50.0)));
+        assert_eq!(fit.to_canvas(Point::new(0.0, 100.0), window, 1.0), Some(Point::new(0.0, 0.0)));
+        assert_eq!(fit.to_canvas(Point::new(200.0, 50.0), window, 1.0), None);  // ← in the letterbox
+        assert_eq!(fit.to_canvas(Point::new(400.0, 300.0), window, 1.0), None); // ← just past the corner
+    }
+
+    #[test]
+    fn lock_aspect_keeps_the_dragged_side() {
+        let mut fit = fit(FitMode::Contain);
+        assert_eq!(fit.lock_aspect((400, 300)), Some((400, 200)));
+        assert_eq!(fit.lock_aspect((400, 260)), Some((520, 260))); // ← height was dragged
+        assert_eq!(fit.lock_aspect((520, 260)), None);
+        assert_eq!(fit.lock_aspect((600, 250)), Some((600, 300))); // ← width was dragged further
+        assert_eq!(fit.lock_aspect((601, 300)), None);             // ← a pixel off is close enough
+        assert_eq!(fit.lock_aspect((0, 0)), None);
+    }
+
+    #[test]
+    fn lock_aspect_off() {
+        let mut fit = CanvasFit::new((200, 100), FitMode::Contain, Color::BLACK, false);
+        assert_eq!(fit.lock_aspect((400, 300)), None);
+    }
+}
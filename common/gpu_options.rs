@@ -0,0 +1,149 @@
+use std::fmt;
+use skia_safe::gpu::{ContextOptions, context_options::Enable};
+
+// The knobs from Skia's ContextOptions that are worth turning when tracking down driver-specific
+// rendering bugs. Anything left as None keeps Skia's own default.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GpuOptions {
+    pub path_msaa: Option<i32>,              // sample count for paths drawn with internal MSAA
+    pub glyph_cache_bytes: usize,            // the most the glyph atlas can grow to
+    pub distance_field_paths: bool,
+    pub path_mask_caching: bool,
+    pub gl_error_checks: Option<bool>,
+    pub driver_workarounds: bool,
+    pub print_shader_errors: bool,
+    pub reduce_task_splitting: Option<bool>,
+}
+
+impl Default for GpuOptions {
+    // Skia's defaults assume one context has the GPU to itself. With several windows (each with
+    // their own glyph atlas) the atlas is capped at half its usual size, and ops are batched into
+    // fewer render passes.
+    fn default() -> Self {
+        GpuOptions{
+            path_msaa: None,
+            glyph_cache_bytes: 4 * 1024 * 1024,
+            distance_field_paths: true,
+            path_mask_caching: true,
+            gl_error_checks: None,
+            driver_workarounds: true,
+            print_shader_errors: true,
+            reduce_task_splitting: Some(true),
+        }
+    }
+}
+
+// Parse a comma-separated list of `name=value` pairs, e.g., `msaa=4,workarounds=off`
+impl std::str::FromStr for GpuOptions {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut options = GpuOptions::default();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').ok_or_else(|| format!("Expected name=value, not {:?}", pair))?;
+            let flag = || match value {
+                "on" | "yes" | "true" | "1" => Ok(true),
+                "off" | "no" | "false" | "0" => Ok(false),
+                _ => Err(format!("Expected on or off for {}, not {:?}", name, value))
+            };
+            let number = || value.parse::<usize>().map_err(|_| format!("Expected a number for {}, not {:?}", name, value));
+            match name {
+                "msaa" => options.path_msaa = Some(number()? as i32),
+                "glyph-cache-mb" => options.glyph_cache_bytes = number()? * 1024 * 1024,
+                "distance-field-paths" => options.distance_field_paths = flag()?,
+                "path-mask-caching" => options.path_mask_caching = flag()?,
+                "gl-error-checks" => options.gl_error_checks = Some(flag()?),
+                "workarounds" => options.driver_workarounds = flag()?,
+                "shader-errors" => options.print_shader_errors = flag()?,
+                "reduce-task-splitting" => options.reduce_task_splitting = Some(flag()?),
+                _ => return Err(format!("Unknown GPU option: {:?}", name))
+            }
+        }
+        Ok(options)
+    }
+}
+
+impl GpuOptions {
+    pub fn context_options(&self) -> ContextOptions {
+        let enable = |flag:Option<bool>| match flag {
+            Some(true) => Enable::Yes,
+            Some(false) => Enable::No,
+            None => Enable::Default,
+        };
+
+        let mut options = ContextOptions::new();
+        if let Some(samples) = self.path_msaa {
+            options.internal_multisample_count = samples;
+        }
+        options.glyph_cache_texture_maximum_bytes = self.glyph_cache_bytes;
+        options.disable_distance_field_paths = !self.distance_field_paths;
+        options.allow_path_mask_caching = self.path_mask_caching;
+        options.skip_gl_error_checks = enable(self.gl_error_checks.map(|checks| !checks));
+        options.disable_driver_correctness_workarounds = !self.driver_workarounds;
+        options.suppress_prints = !self.print_shader_errors;
+        options.reduce_ops_task_splitting = enable(self.reduce_task_splitting);
+        options
+    }
+}
+
+impl fmt::Display for GpuOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let on = |flag:bool| if flag { "on" } else { "off" };
+        let maybe = |flag:Option<bool>| flag.map(on).unwrap_or("default");
+        write!(f, "msaa={} glyph-cache-mb={} distance-field-paths={} path-mask-caching={} gl-error-checks={} workarounds={} shader-errors={} reduce-task-splitting={}",
+            self.path_msaa.map(|n| n.to_string()).unwrap_or_else(|| "default".to_string()),
+            self.glyph_cache_bytes / (1024 * 1024), on(self.distance_field_paths), on(self.path_mask_caching),
+            maybe(self.gl_error_checks), on(self.driver_workarounds), on(self.print_shader_errors),
+            maybe(self.reduce_task_splitting))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_is_default() {
+        assert_eq!("".parse::<GpuOptions>(), Ok(GpuOptions::default()));
+        assert_eq!(" , ,".parse::<GpuOptions>(), Ok(GpuOptions::default()));
+    }
+
+    #[test]
+    fn parses_every_option() {
+        let options:GpuOptions = "msaa=4, glyph-cache-mb=16,distance-field-paths=off,path-mask-caching=no,\
+            gl-error-checks=on,workarounds=false,shader-errors=0,reduce-task-splitting=off".parse().unwrap();
+        assert_eq!(options, GpuOptions{
+            path_msaa: Some(4),
+            glyph_cache_bytes: 16 * 1024 * 1024,
+            distance_field_paths: false,
+            path_mask_caching: false,
+            gl_error_checks: Some(true),
+            driver_workarounds: false,
+            print_shader_errors: false,
+            reduce_task_splitting: Some(false),
+        });
+    }
+
+    #[test]
+    fn later_pairs_win() {
+        let options:GpuOptions = "msaa=2,msaa=8".parse().unwrap();
+        assert_eq!(options.path_msaa, Some(8));
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!("msaa".parse::<GpuOptions>().is_err());
+        assert!("msaa=four".parse::<GpuOptions>().is_err());
+        assert!("msaa=-1".parse::<GpuOptions>().is_err());
+        assert!("workarounds=maybe".parse::<GpuOptions>().is_err());
+        assert!("wireframe=on".parse::<GpuOptions>().is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let options:GpuOptions = "msaa=4,gl-error-checks=off".parse().unwrap();
+        let shown = options.to_string();
+        assert!(shown.starts_with("msaa=4 glyph-cache-mb=4 "));
+        let reparsed:GpuOptions = shown.split(' ').filter(|pair| !pair.ends_with("=default")).collect::<Vec<_>>().join(",").parse().unwrap();
+        assert_eq!(reparsed, options);
+    }
+}
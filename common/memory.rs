@@ -0,0 +1,123 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use skia_safe::gpu::DirectContext;
+
+// Skia's default limit for a single context, so one window gets the same cache it always did
+pub const DEFAULT_BUDGET: usize = 256 * 1024 * 1024;
+
+// resources that no draw has touched in this long get released, even in windows still on screen
+pub const IDLE_PURGE: Duration = Duration::from_secs(5);
+
+static BUDGET: AtomicUsize = AtomicUsize::new(DEFAULT_BUDGET);
+static WINDOWS: AtomicUsize = AtomicUsize::new(0);
+
+// Set the total GPU memory (in bytes) that every window's resource cache has to fit within
+pub fn set_budget(bytes:usize){
+    BUDGET.store(bytes, Ordering::Relaxed);
+}
+
+pub fn budget() -> usize {
+    BUDGET.load(Ordering::Relaxed)
+}
+
+// each open window's slice of the budget
+pub fn share() -> usize {
+    budget() / WINDOWS.load(Ordering::Relaxed).max(1)
+}
+
+// A window's claim on the shared budget. The budget is re-split whenever windows open or close,
+// and each window picks up its new limit the next time it calls `apply`.
+pub struct BudgetShare {
+    limit: usize,
+}
+
+impl BudgetShare {
+    pub fn new() -> Self {
+        WINDOWS.fetch_add(1, Ordering::Relaxed);
+        BudgetShare{ limit: 0 }
+    }
+
+    // Call once per frame (with the context current): applies the window's current share of the
+    // budget and releases anything that's gone unused for IDLE_PURGE
+    pub fn apply(&mut self, context:&mut DirectContext){
+        let limit = share();
+        if limit != self.limit {
+            context.set_resource_cache_limit(limit);
+            self.limit = limit;
+        }
+        context.perform_deferred_cleanup(IDLE_PURGE, None);
+    }
+}
+
+impl Drop for BudgetShare {
+    fn drop(&mut self) {
+        WINDOWS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryUsage {
+    pub resources: usize,
+    pub bytes: usize,
+    pub purgeable: usize,
+    pub limit: usize,
+}
+
+impl MemoryUsage {
+    pub fn of(context:&DirectContext) -> Self {
+        let usage = context.resource_cache_usage();
+        MemoryUsage{
+            resources: usage.resource_count,
+            bytes: usage.resource_bytes,
+            purgeable: context.resource_cache_purgeable_bytes(),
+            limit: context.resource_cache_limit(),
+        }
+    }
+}
+
+impl std::ops::Add for MemoryUsage {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        MemoryUsage{
+            resources: self.resources + other.resources,
+            bytes: self.bytes + other.bytes,
+            purgeable: self.purgeable + other.purgeable,
+            limit: self.limit + other.limit,
+        }
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mb = |bytes:usize| bytes as f64 / (1024.0 * 1024.0);
+        write!(f, "{} resources, {:.1} MB ({:.1} MB purgeable) of {:.1} MB",
+            self.resources, mb(self.bytes), mb(self.purgeable), mb(self.limit))
+    }
+}
+
+// Resource cache usage for each window's context (listed by the ids shown in their HUDs)
+#[derive(Clone, Debug, Default)]
+pub struct MemoryReport {
+    pub contexts: Vec<(usize, MemoryUsage)>,
+}
+
+impl MemoryReport {
+    pub fn new(mut contexts:Vec<(usize, MemoryUsage)>) -> Self {
+        contexts.sort_by_key(|(id, _)| *id);
+        MemoryReport{ contexts }
+    }
+
+    pub fn total(&self) -> MemoryUsage {
+        self.contexts.iter().fold(MemoryUsage::default(), |sum, (_, usage)| sum + *usage)
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (id, usage) in &self.contexts {
+            writeln!(f, "  context {}: {}", id, usage)?;
+        }
+        write!(f, "  total: {} (budget {:.1} MB)", self.total(), budget() as f64 / (1024.0 * 1024.0))
+    }
+}
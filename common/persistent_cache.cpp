@@ -0,0 +1,67 @@
+// skia-bindings ships prebuilt libraries without Skia's headers, and leaves
+// GrContextOptions::PersistentCache opaque. Skia only ever calls a cache through its vtable though,
+// so this declares just enough to build one: the same virtual functions, in the same order, as
+// include/gpu/GrContextOptions.h (unchanged between m100 and m102, i.e., skia-bindings 0.50 and
+// 0.52; build_support.rs refuses to build this against any other version). The sizes are checked
+// against the bindings in shader_cache.rs. The work itself is done by the callbacks there.
+
+class SkData;
+class SkString;
+
+extern "C" void C_SkData_unref(const SkData* self); // ← from skia-bindings
+
+typedef SkData* (*LoadProc)(const SkData* key); // ← a new reference to the data, or null
+typedef void (*StoreProc)(const SkData* key, const SkData* data);
+
+namespace {
+
+// Laid out and returned the same way as sk_sp<SkData>: one pointer, returned indirectly since it
+// has a destructor
+class DataRef {
+public:
+    explicit DataRef(SkData* data) : fData(data) {}
+    DataRef(DataRef&& that) : fData(that.fData) { that.fData = nullptr; }
+    ~DataRef() {
+        if (fData) {
+            C_SkData_unref(fData);
+        }
+    }
+
+private:
+    SkData* fData;
+};
+
+static_assert(sizeof(DataRef) == sizeof(void*), "DataRef has to stand in for sk_sp<SkData>");
+
+class PersistentCache {
+public:
+    PersistentCache(LoadProc load, StoreProc store) : fLoad(load), fStore(store) {}
+
+    virtual ~PersistentCache() = default;
+
+    virtual DataRef load(const SkData& key) {
+        return DataRef(fLoad(&key));
+    }
+
+    virtual void store(const SkData& key, const SkData& data) {
+        fStore(&key, &data);
+    }
+
+    virtual void store(const SkData& key, const SkData& data, const SkString& /*description*/) {
+        fStore(&key, &data);
+    }
+
+private:
+    LoadProc fLoad;
+    StoreProc fStore;
+};
+
+} // namespace
+
+extern "C" void* persistent_cache_new(LoadProc load, StoreProc store) {
+    return new PersistentCache(load, store);
+}
+
+extern "C" void persistent_cache_delete(void* cache) {
+    delete static_cast<PersistentCache*>(cache);
+}
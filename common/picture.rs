@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+use skia_safe::{Canvas, Color, EncodedImageFormat, IRect, Picture, PictureRecorder, Rect, RoundOut, Surface};
+
+use crate::capture;
+
+// Captures a frame's drawing commands as an SkPicture, which is then played back into the window.
+// When a frame comes out wrong, the saved picture shows whether the commands were at fault or
+// something went awry in getting them onto the screen.
+pub struct FrameRecorder {
+    recorder: PictureRecorder,
+    recording: bool,
+    last: Option<Picture>,
+}
+
+impl FrameRecorder {
+    pub fn new() -> Self {
+        FrameRecorder{ recorder: PictureRecorder::new(), recording: false, last: None }
+    }
+
+    pub fn begin(&mut self, (width, height):(i32, i32)){
+        self.recorder.begin_recording(Rect::from_iwh(width, height), None);
+        self.recording = true;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    pub fn canvas(&mut self) -> Option<&mut Canvas> {
+        self.recorder.recording_canvas()
+    }
+
+    // Stop recording, hanging on to the picture (until the next one is finished) so it can be saved
+    pub fn finish(&mut self) -> Option<Picture> {
+        if !std::mem::take(&mut self.recording) {
+            return None
+        }
+        self.last = self.recorder.finish_recording_as_picture(None);
+        self.last.clone()
+    }
+
+    pub fn last(&self) -> Option<&Picture> {
+        self.last.as_ref()
+    }
+}
+
+// Save a picture as `<prefix>-<timestamp>.skp` (which can be opened in the Skia debugger at
+// https://debugger.skia.org) along with a PNG of it drawn by Skia's CPU rasterizer. If the PNG
+// looks right but the window didn't, the problem lies with the GPU backend.
+pub fn save(picture:&Picture, prefix:&str) -> Result<(PathBuf, PathBuf), String> {
+    let bounds:IRect = picture.cull_rect().round_out();
+    let mut surface = Surface::new_raster_n32_premul((bounds.width(), bounds.height()))
+        .ok_or_else(|| "Could not create a raster surface".to_string())?;
+    let canvas = surface.canvas();
+    canvas.clear(Color::TRANSPARENT);
+    canvas.translate((-bounds.left as f32, -bounds.top as f32));
+    canvas.draw_picture(picture, None, None);
+    let format = EncodedImageFormat::PNG;
+    let png = capture::encode(&surface.image_snapshot(), format, 100)?;
+
+    let stamp = capture::timestamp();
+    let skp_path = PathBuf::from(format!("{}-{}.skp", prefix, stamp));
+    let png_path = PathBuf::from(format!("{}-{}.{}", prefix, stamp, capture::extension(format)));
+    std::fs::write(&skp_path, picture.serialize().as_bytes())
+        .and_then(|_| std::fs::write(&png_path, png.as_bytes()))
+        .map_err(|e| format!("Could not save picture: {}", e))?;
+    Ok((skp_path, png_path))
+}
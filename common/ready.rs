@@ -0,0 +1,17 @@
+use std::io::Write;
+use std::sync::Once;
+
+// The line a backend prints on stdout once a window has presented its first frame. The launcher
+// only settles on a backend after reading it, so nothing else may print exactly this.
+pub const FIRST_FRAME: &str = "skia-win: first frame presented";
+
+static REPORTED: Once = Once::new();
+
+// Report the first frame to have been presented by any of the windows (later calls do nothing)
+pub fn first_frame_presented() {
+    REPORTED.call_once(|| {
+        let mut stdout = std::io::stdout();
+        let _ = writeln!(stdout, "{}", FIRST_FRAME);
+        let _ = stdout.flush(); // ← stdout is a pipe when run by the launcher
+    });
+}
@@ -0,0 +1,332 @@
+use std::path::PathBuf;
+use skia_safe::{EncodedImageFormat, Image};
+
+// every recorded frame is given the same duration regardless of how long it actually took to
+// render, so captures of the (frame-stepped) animations are reproducible from run to run
+pub const FRAME_DELAY_MS: u16 = 1000 / 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordingFormat {
+    Sequence, // numbered PNGs
+    Apng,
+    Webp,
+}
+
+impl std::str::FromStr for RecordingFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sequence" | "png" => Ok(RecordingFormat::Sequence),
+            "apng" => Ok(RecordingFormat::Apng),
+            "webp" => Ok(RecordingFormat::Webp),
+            _ => Err(format!("Unknown recording format: {:?}", s))
+        }
+    }
+}
+
+// Collects a window's frames as they're read back. Image sequences are written out frame by
+// frame; animations are buffered (as already-compressed frames) and written when the recorder
+// is dropped, which happens once the window has stopped recording *and* every readback that was
+// still in flight has been delivered.
+pub struct Recorder {
+    format: RecordingFormat,
+    prefix: String,
+    size: Option<(i32, i32)>,
+    frames: Vec<Vec<u8>>,
+    count: usize,
+}
+
+impl Recorder {
+    pub fn new(format:RecordingFormat, prefix:&str) -> Self {
+        Recorder{ format, prefix: prefix.to_string(), size: None, frames: vec![], count: 0 }
+    }
+
+    pub fn add_frame(&mut self, image:&Image) -> Result<(), String> {
+        // animations can't change size partway through so drop any frames that don't match
+        let size = (image.width(), image.height());
+        if self.format != RecordingFormat::Sequence && *self.size.get_or_insert(size) != size {
+            return Err(format!("Skipping {}×{} frame in {}×{} recording", size.0, size.1, self.size.unwrap().0, self.size.unwrap().1))
+        }
+
+        let encoding = match self.format {
+            RecordingFormat::Webp => EncodedImageFormat::WEBP,
+            _ => EncodedImageFormat::PNG,
+        };
+        let data = image.encode_to_data_with_quality(encoding, 100)
+            .ok_or_else(|| format!("Could not encode frame {}", self.count))?;
+
+        self.count += 1;
+        match self.format {
+            RecordingFormat::Sequence => {
+                let path = format!("{}-{:05}.png", self.prefix, self.count);
+                std::fs::write(&path, data.as_bytes()).map_err(|e| format!("{}: {}", path, e))
+            },
+            _ => {
+                self.frames.push(data.as_bytes().to_vec());
+                Ok(())
+            }
+        }
+    }
+
+    pub fn finish(&mut self) -> Result<Option<PathBuf>, String> {
+        let frames = std::mem::take(&mut self.frames);
+        let (bytes, ext) = match (self.format, self.size) {
+            (RecordingFormat::Apng, Some(_)) => (apng(&frames)?, "png"),
+            (RecordingFormat::Webp, Some((width, height))) => (animated_webp(&frames, width, height)?, "webp"),
+            _ => return Ok(None)
+        };
+        let path = PathBuf::from(format!("{}.{}", self.prefix, ext));
+        std::fs::write(&path, bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Some(path))
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        match self.finish() {
+            Ok(Some(path)) => println!("Saved {} frames to {}", self.count, path.display()),
+            Ok(None) => println!("Saved {} frames to {}-*.png", self.count, self.prefix),
+            Err(msg) => eprintln!("Could not save recording: {}", msg),
+        }
+    }
+}
+
+// Split a PNG or RIFF file into its (type, data) chunks
+fn png_chunks(png:&[u8]) -> Result<Vec<([u8; 4], &[u8])>, String> {
+    let mut chunks = vec![];
+    let mut pos = 8; // skip the signature
+    while pos + 12 <= png.len() {
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+        let kind:[u8; 4] = png[pos + 4..pos + 8].try_into().unwrap();
+        let data = png.get(pos + 8..pos + 8 + len).ok_or("Truncated PNG chunk")?;
+        chunks.push((kind, data));
+        pos += 12 + len;
+    }
+    Ok(chunks)
+}
+
+fn riff_chunks(webp:&[u8]) -> Result<Vec<([u8; 4], &[u8])>, String> {
+    let mut chunks = vec![];
+    let mut pos = 12; // skip the RIFF header
+    while pos + 8 <= webp.len() {
+        let kind:[u8; 4] = webp[pos..pos + 4].try_into().unwrap();
+        let len = u32::from_le_bytes(webp[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let data = webp.get(pos + 8..pos + 8 + len).ok_or("Truncated WebP chunk")?;
+        chunks.push((kind, data));
+        pos += 8 + len + (len & 1);
+    }
+    Ok(chunks)
+}
+
+fn crc32(bytes:&[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB88320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+fn write_png_chunk(out:&mut Vec<u8>, kind:&[u8; 4], data:&[u8]){
+    out.extend((data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32(&out[start..]);
+    out.extend(crc.to_be_bytes());
+}
+
+fn write_riff_chunk(out:&mut Vec<u8>, kind:&[u8; 4], data:&[u8]){
+    out.extend(kind);
+    out.extend((data.len() as u32).to_le_bytes());
+    out.extend(data);
+    if data.len() & 1 == 1 {
+        out.push(0);
+    }
+}
+
+// Assemble an APNG from individually encoded PNG frames: the first frame's IDAT chunks double
+// as the default image and every later frame's image data is re-labelled as fdAT chunks
+fn apng(frames:&[Vec<u8>]) -> Result<Vec<u8>, String> {
+    let first = png_chunks(frames.first().ok_or("No frames were recorded")?)?;
+    let ihdr = first.iter().find(|(kind, _)| kind == b"IHDR").ok_or("Missing IHDR chunk")?.1;
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_png_chunk(&mut out, b"IHDR", ihdr);
+
+    let mut actl = (frames.len() as u32).to_be_bytes().to_vec();
+    actl.extend(0u32.to_be_bytes()); // loop forever
+    write_png_chunk(&mut out, b"acTL", &actl);
+
+    let mut seq = 0u32;
+    for (i, frame) in frames.iter().enumerate() {
+        let mut fctl = seq.to_be_bytes().to_vec();
+        fctl.extend(&ihdr[0..8]); // width & height
+        fctl.extend([0u8; 8]);    // x & y offset
+        fctl.extend(FRAME_DELAY_MS.to_be_bytes());
+        fctl.extend(1000u16.to_be_bytes());
+        fctl.extend([0u8, 0u8]);  // dispose: none, blend: source
+        write_png_chunk(&mut out, b"fcTL", &fctl);
+        seq += 1;
+
+        for (kind, data) in png_chunks(frame)? {
+            if &kind != b"IDAT" { continue }
+            match i {
+                0 => write_png_chunk(&mut out, b"IDAT", data),
+                _ => {
+                    let mut fdat = seq.to_be_bytes().to_vec();
+                    fdat.extend(data);
+                    write_png_chunk(&mut out, b"fdAT", &fdat);
+                    seq += 1;
+                }
+            }
+        }
+    }
+    write_png_chunk(&mut out, b"IEND", &[]);
+    Ok(out)
+}
+
+// Assemble an animated WebP by wrapping each still frame's bitstream (VP8/VP8L plus any ALPH
+// chunk) in an ANMF chunk
+fn animated_webp(frames:&[Vec<u8>], width:i32, height:i32) -> Result<Vec<u8>, String> {
+    let u24 = |n:u32| n.to_le_bytes()[0..3].to_vec();
+
+    let mut body = b"WEBP".to_vec();
+    let mut vp8x = vec![0x02 | 0x10, 0, 0, 0]; // animation & alpha flags
+    vp8x.extend(u24(width as u32 - 1));
+    vp8x.extend(u24(height as u32 - 1));
+    write_riff_chunk(&mut body, b"VP8X", &vp8x);
+
+    let mut anim = 0xFFFFFFFFu32.to_le_bytes().to_vec(); // background color
+    anim.extend(0u16.to_le_bytes());                    // loop forever
+    write_riff_chunk(&mut body, b"ANIM", &anim);
+
+    for frame in frames {
+        let mut anmf = [u24(0), u24(0), u24(width as u32 - 1), u24(height as u32 - 1)].concat();
+        anmf.extend(u24(FRAME_DELAY_MS as u32));
+        anmf.push(0x02); // don't blend with the previous frame
+        for (kind, data) in riff_chunks(frame)? {
+            if matches!(&kind, b"ALPH" | b"VP8 " | b"VP8L") {
+                write_riff_chunk(&mut anmf, &kind, data);
+            }
+        }
+        write_riff_chunk(&mut body, b"ANMF", &anmf);
+    }
+
+    let mut out = b"RIFF".to_vec();
+    out.extend((body.len() as u32).to_le_bytes());
+    out.extend(body);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a stand-in for an encoded PNG: only the chunk structure matters when assembling an APNG
+    fn png(width:u32, height:u32, idats:&[&[u8]]) -> Vec<u8> {
+        let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut ihdr = width.to_be_bytes().to_vec();
+        ihdr.extend(height.to_be_bytes());
+        ihdr.extend([8, 6, 0, 0, 0]); // 8-bit RGBA
+        write_png_chunk(&mut out, b"IHDR", &ihdr);
+        write_png_chunk(&mut out, b"tEXt", b"Software\0skia");
+        for idat in idats {
+            write_png_chunk(&mut out, b"IDAT", idat);
+        }
+        write_png_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    // likewise for an encoded WebP, whose chunks are only ever copied
+    fn webp(chunks:&[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        for (kind, data) in chunks {
+            write_riff_chunk(&mut body, kind, data);
+        }
+        let mut out = b"RIFF".to_vec();
+        out.extend((body.len() as u32).to_le_bytes());
+        out.extend(body);
+        out
+    }
+
+    #[test]
+    fn crc32_known_vectors() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b"IEND"), 0xAE426082); // ← the CRC every PNG ends with
+    }
+
+    #[test]
+    fn apng_chunk_layout() {
+        let frames = [png(3, 2, &[b"first", b"more"]), png(3, 2, &[b"second"])];
+        let out = apng(&frames).unwrap();
+        assert_eq!(&out[..8], b"\x89PNG\r\n\x1a\n");
+
+        // every chunk's length has to cover its data exactly and its CRC its type & data
+        let mut pos = 8;
+        while pos < out.len() {
+            let len = u32::from_be_bytes(out[pos..pos + 4].try_into().unwrap()) as usize;
+            let crc = u32::from_be_bytes(out[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(&out[pos + 4..pos + 8 + len]));
+            pos += 12 + len;
+        }
+        assert_eq!(pos, out.len());
+
+        let chunks = png_chunks(&out).unwrap();
+        let kinds:Vec<&[u8]> = chunks.iter().map(|(kind, _)| &kind[..]).collect();
+        assert_eq!(kinds, [&b"IHDR"[..], b"acTL", b"fcTL", b"IDAT", b"IDAT", b"fcTL", b"fdAT", b"IEND"]);
+
+        assert_eq!(chunks[0].1, png_chunks(&frames[0]).unwrap()[0].1);
+        assert_eq!(chunks[1].1, [0, 0, 0, 2, 0, 0, 0, 0]); // two frames, looping forever
+
+        // fcTL & fdAT share one sequence, which the default image's IDATs aren't part of
+        let seq = |data:&[u8]| u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let (first, second) = (chunks[2].1, chunks[5].1);
+        assert_eq!((seq(first), seq(second), seq(chunks[6].1)), (0, 1, 2));
+        assert_eq!(first.len(), 26);
+        assert_eq!(&first[4..12], [0, 0, 0, 3, 0, 0, 0, 2]);
+        assert_eq!(&first[20..24], [(FRAME_DELAY_MS >> 8) as u8, FRAME_DELAY_MS as u8, 0x03, 0xE8]);
+        assert_eq!(&first[4..], &second[4..]);
+
+        assert_eq!((chunks[3].1, chunks[4].1), (&b"first"[..], &b"more"[..]));
+        assert_eq!(&chunks[6].1[4..], b"second");
+    }
+
+    #[test]
+    fn webp_riff_layout() {
+        let frames = [
+            webp(&[(b"VP8X", &[0; 10]), (b"ALPH", b"abc"), (b"VP8 ", b"frame"), (b"EXIF", b"xy")]),
+            webp(&[(b"VP8L", b"odd")]),
+        ];
+        let out = animated_webp(&frames, 300, 2).unwrap();
+        assert_eq!(&out[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(out[4..8].try_into().unwrap()) as usize, out.len() - 8);
+        assert_eq!(&out[8..12], b"WEBP");
+        assert_eq!(out.len() % 2, 0);
+
+        let chunks = riff_chunks(&out).unwrap();
+        let kinds:Vec<&[u8]> = chunks.iter().map(|(kind, _)| &kind[..]).collect();
+        assert_eq!(kinds, [&b"VP8X"[..], b"ANIM", b"ANMF", b"ANMF"]);
+
+        // canvas size is stored less one, in 24 bits
+        assert_eq!(chunks[0].1, [0x12, 0, 0, 0, 0x2B, 0x01, 0, 0x01, 0, 0]);
+        assert_eq!(chunks[1].1, [0xFF, 0xFF, 0xFF, 0xFF, 0, 0]);
+
+        // each frame keeps only its bitstream chunks, padded to an even length
+        let (first, second) = (chunks[2].1, chunks[3].1);
+        assert_eq!(&first[..16], [0, 0, 0, 0, 0, 0, 0x2B, 0x01, 0, 0x01, 0, 0, FRAME_DELAY_MS as u8, 0, 0, 0x02]);
+        assert_eq!(first.len(), 16 + (8 + 3 + 1) + (8 + 5 + 1));
+        assert_eq!(second.len(), 16 + (8 + 3 + 1));
+        assert_eq!(&second[16..], b"VP8L\x03\0\0\0odd\0");
+
+        let nested = |anmf:&[u8]| {
+            let mut frame = b"RIFF\0\0\0\0WEBP".to_vec();
+            frame.extend(&anmf[16..]);
+            riff_chunks(&frame).unwrap().into_iter().map(|(kind, data)| (kind, data.to_vec())).collect::<Vec<_>>()
+        };
+        assert_eq!(nested(first), [(*b"ALPH", b"abc".to_vec()), (*b"VP8 ", b"frame".to_vec())]);
+    }
+}
@@ -0,0 +1,222 @@
+use std::ffi::c_void;
+use std::fs;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use skia_bindings as sb;
+use skia_safe::gpu::ContextOptions;
+use skia_safe::wrapper::NativeTransmutableWrapper;
+
+// the version of skia-safe the demo was built with (see build.rs)
+const SKIA_VERSION: &str = env!("SKIA_SAFE_VERSION");
+
+// Compiled shaders are cached at two levels. The drivers keep caches of their own (Mesa's GL and
+// Vulkan drivers, lavapipe, and NVIDIA's), keyed by the driver build and the shader source Skia
+// generates, and these are pointed at a directory per backend and Skia version. On top of that,
+// Skia is handed a PersistentCache (see persistent_cache.cpp) that saves what it would otherwise
+// have to generate & compile again: program binaries on GL, SPIR-V and the VkPipelineCache's
+// contents on Vulkan, and MSL on Metal.
+pub struct ShaderCache {
+    pub dir: PathBuf,
+    bytes_at_start: u64,
+}
+
+// Skia's cache of programs: one directory per driver, since one driver can't load another's binaries
+struct Programs {
+    dir: PathBuf,
+    driver: Option<(String, PathBuf)>, // ← the driver the contexts are using & its directory
+    cache: Weak<ProgramCache>,         // ← the PersistentCache object, while any context has it
+}
+
+static PROGRAMS: Mutex<Option<Programs>> = Mutex::new(None);
+
+// The PersistentCache object handed to Skia. Every context given one has to keep hold of it until
+// the context itself is gone; it's deleted once the last one lets go.
+pub struct ProgramCache(*mut c_void);
+
+// the callbacks only touch files and atomics, so the contexts on any thread can share one
+unsafe impl Send for ProgramCache {}
+unsafe impl Sync for ProgramCache {}
+
+impl Drop for ProgramCache {
+    fn drop(&mut self){
+        unsafe { persistent_cache_delete(self.0) }
+    }
+}
+
+// What the shim in persistent_cache.cpp stands in for has to be laid out the way it assumes: a
+// PersistentCache with no data members besides its vtable pointer, and an sk_sp<SkData> that's a
+// single pointer. ContextOptions is reached through skia-safe's wrapper, so it has to match the
+// GrContextOptions skia-bindings declares too.
+const _: () = assert!(size_of::<sb::GrContextOptions_PersistentCache>() == size_of::<*const c_void>());
+const _: () = assert!(size_of::<sb::sk_sp<sb::SkData>>() == size_of::<*mut sb::SkData>());
+const _: () = assert!(size_of::<ContextOptions>() == size_of::<sb::GrContextOptions>());
+
+static LOADS: AtomicUsize = AtomicUsize::new(0);
+static HITS: AtomicUsize = AtomicUsize::new(0);
+static STORES: AtomicUsize = AtomicUsize::new(0);
+
+type LoadProc = extern "C" fn(key:*const sb::SkData) -> *mut sb::SkData;
+type StoreProc = extern "C" fn(key:*const sb::SkData, data:*const sb::SkData);
+
+extern "C" {
+    fn persistent_cache_new(load:LoadProc, store:StoreProc) -> *mut c_void;
+    fn persistent_cache_delete(cache:*mut c_void);
+}
+
+// SHADER_CACHE can name the directory to use, or be set to `off` to leave the drivers' defaults
+// alone (and not give Skia a cache). The drivers read their settings from the environment, so this
+// has to be called at the very start of `main`, before any other threads exist.
+pub fn init(backend:&str) -> Option<ShaderCache> {
+    let dir = match std::env::var_os("SHADER_CACHE") {
+        Some(dir) if dir == "off" => return None,
+        Some(dir) => PathBuf::from(dir),
+        None => default_dir()?,
+    }.join(backend).join(format!("skia-{}", SKIA_VERSION));
+
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("Could not create shader cache {}: {}", dir.display(), e);
+        return None
+    }
+
+    std::env::set_var("MESA_SHADER_CACHE_DIR", &dir);
+    std::env::set_var("MESA_GLSL_CACHE_DIR", &dir); // ← the name older versions of Mesa look for
+    std::env::set_var("__GL_SHADER_DISK_CACHE", "1");
+    std::env::set_var("__GL_SHADER_DISK_CACHE_PATH", &dir);
+    std::env::set_var("__GL_SHADER_DISK_CACHE_SKIP_CLEANUP", "1");
+
+    *PROGRAMS.lock().unwrap() = Some(Programs{ dir: dir.join("skia"), driver: None, cache: Weak::new() });
+
+    let bytes_at_start = dir_size(&dir);
+    Some(ShaderCache{ dir, bytes_at_start })
+}
+
+// Give Skia the program cache in the options for a new context, keyed by the `driver` behind it
+// (every window is expected to be using the same one). The context has to be dropped before the
+// cache that's returned.
+pub fn attach(options:&mut ContextOptions, driver:&str) -> Option<Arc<ProgramCache>> {
+    let mut programs = PROGRAMS.lock().unwrap();
+    let programs = programs.as_mut()?; // ← the cache is off
+
+    match &programs.driver {
+        Some((used, _)) if used != driver => {
+            eprintln!("Not caching programs for {}, the cache is in use by {}", driver, used);
+            return None
+        }
+        Some(_) => {}
+        None => {
+            let dir = programs.dir.join(format!("{:016x}", fnv1a(driver.as_bytes())));
+            if let Err(e) = fs::create_dir_all(&dir) {
+                eprintln!("Could not create program cache {}: {}", dir.display(), e);
+                return None
+            }
+            programs.driver = Some((driver.to_string(), dir));
+        }
+    }
+
+    let cache = programs.cache.upgrade().unwrap_or_else(|| {
+        let cache = Arc::new(ProgramCache(unsafe { persistent_cache_new(load, store) }));
+        programs.cache = Arc::downgrade(&cache);
+        cache
+    });
+
+    // skia-safe doesn't wrap the PersistentCache field, but hands out the GrContextOptions underneath
+    options.inner_mut().fPersistentCache = cache.0 as *mut sb::GrContextOptions_PersistentCache;
+    Some(cache)
+}
+
+fn default_dir() -> Option<PathBuf> {
+    let base = if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| Path::new(&home).join("Library/Caches"))
+    } else if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    base.map(|base| base.join("skia-win").join("shaders"))
+}
+
+// The drivers don't report hits, but anything they had to compile gets added to the cache. Some
+// keep one file per shader and others a single database, so go by size rather than file count.
+fn dir_size(dir:&Path) -> u64 {
+    fs::read_dir(dir).map(|entries| entries.flatten().map(|entry| {
+        match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        }
+    }).sum()).unwrap_or(0)
+}
+
+// a stable hash for file names (unlike std's, which can change from one Rust release to the next)
+fn fnv1a(bytes:&[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+unsafe fn bytes<'a>(data:*const sb::SkData) -> &'a [u8] {
+    match (*data).fSize {
+        0 => &[],
+        size => std::slice::from_raw_parts((*data).fPtr as *const u8, size)
+    }
+}
+
+// Each program is saved to a file named for its key's hash, holding the key's length, the key
+// itself (to rule out collisions), and then the program
+fn path_for(key:&[u8]) -> Option<PathBuf> {
+    let programs = PROGRAMS.lock().ok()?;
+    let (_, dir) = programs.as_ref()?.driver.as_ref()?;
+    Some(dir.join(format!("{:016x}", fnv1a(key))))
+}
+
+extern "C" fn load(key:*const sb::SkData) -> *mut sb::SkData {
+    LOADS.fetch_add(1, Ordering::Relaxed);
+    let key = unsafe { bytes(key) };
+    let contents = match path_for(key).and_then(|path| fs::read(path).ok()) {
+        Some(contents) => contents,
+        None => return std::ptr::null_mut()
+    };
+
+    let stored_key = contents.get(..4).map(|len| u32::from_le_bytes(len.try_into().unwrap()) as usize)
+        .and_then(|len| contents.get(4..4 + len));
+    if stored_key != Some(key) {
+        return std::ptr::null_mut()
+    }
+    HITS.fetch_add(1, Ordering::Relaxed);
+    let program = &contents[4 + key.len()..];
+    unsafe { sb::C_SkData_MakeWithCopy(program.as_ptr() as *const _, program.len()) }
+}
+
+extern "C" fn store(key:*const sb::SkData, data:*const sb::SkData) {
+    let (key, data) = unsafe { (bytes(key), bytes(data)) };
+    let path = match path_for(key) {
+        Some(path) => path,
+        None => return
+    };
+
+    let mut contents = Vec::with_capacity(4 + key.len() + data.len());
+    contents.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    contents.extend_from_slice(key);
+    contents.extend_from_slice(data);
+
+    // write to a temporary file first so another run never reads half a program
+    let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+    match fs::write(&temp, &contents).and_then(|_| fs::rename(&temp, &path)) {
+        Ok(_) => { STORES.fetch_add(1, Ordering::Relaxed); }
+        Err(e) => eprintln!("Could not save program {}: {}", path.display(), e),
+    }
+}
+
+impl ShaderCache {
+    // How much was cached when the run started and how much had to be compiled (and added) since,
+    // along with how many of the programs Skia asked for were found in its cache. A warm cache that
+    // hardly grows means nearly every shader the windows needed was a hit.
+    pub fn report(&self) -> String {
+        let kb = |bytes:u64| bytes as f64 / 1024.0;
+        let added = dir_size(&self.dir).saturating_sub(self.bytes_at_start);
+        format!("{:.1} KB cached at startup, {:.1} KB compiled & added this run; Skia loaded {} of {} programs & stored {} ({})",
+            kb(self.bytes_at_start), kb(added), HITS.load(Ordering::Relaxed), LOADS.load(Ordering::Relaxed),
+            STORES.load(Ordering::Relaxed), self.dir.display())
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use skia_safe::{Canvas, Color, Font, Paint, Rect, Typeface};
+
+// how many frames' worth of timings to keep around for the averages & the HUD's graph
+const HISTORY: usize = 120;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTiming {
+    pub record: Duration,  // CPU time spent issuing drawing commands to the canvas
+    pub flush: Duration,   // time spent flushing/submitting work to the GPU
+    pub present: Duration, // time spent swapping/presenting (including any vsync stall)
+}
+
+impl FrameTiming {
+    pub fn total(&self) -> Duration {
+        self.record + self.flush + self.present
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    pub frames: usize,
+    pub dropped: usize,
+    pub surface_recreations: usize,
+    pub context_losses: usize,
+    history: VecDeque<(Instant, FrameTiming)>,
+    gpu_history: VecDeque<Duration>,
+}
+
+impl FrameStats {
+    pub fn add_frame(&mut self, timing:FrameTiming){
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back((Instant::now(), timing));
+        self.frames += 1;
+    }
+
+    // GPU timings arrive separately, a few frames after the frames they measured were drawn. Only
+    // the backends with timer queries call this; everywhere else the history just stays empty.
+    #[allow(dead_code)]
+    pub fn add_gpu_time(&mut self, elapsed:Duration){
+        if self.gpu_history.len() == HISTORY {
+            self.gpu_history.pop_front();
+        }
+        self.gpu_history.push_back(elapsed);
+    }
+
+    // the average time the GPU spent per frame (or None if the backend can't measure it)
+    pub fn gpu_time(&self) -> Option<Duration> {
+        match self.gpu_history.len() {
+            0 => None,
+            n => Some(self.gpu_history.iter().sum::<Duration>() / n as u32)
+        }
+    }
+
+    pub fn average(&self) -> FrameTiming {
+        let n = self.history.len().max(1) as u32;
+        let sum = self.history.iter().fold(FrameTiming::default(), |sum, (_, t)| FrameTiming{
+            record: sum.record + t.record,
+            flush: sum.flush + t.flush,
+            present: sum.present + t.present,
+        });
+        FrameTiming{ record: sum.record / n, flush: sum.flush / n, present: sum.present / n }
+    }
+
+    // frames per second, based on the wall-clock time covered by the rolling history
+    pub fn fps(&self) -> f64 {
+        match (self.history.front(), self.history.back()) {
+            (Some((first, _)), Some((last, _))) if last > first => {
+                (self.history.len() - 1) as f64 / (*last - *first).as_secs_f64()
+            },
+            _ => 0.0
+        }
+    }
+
+    // the intervals between successive frames (oldest first)
+    pub fn frame_intervals(&self) -> Vec<Duration> {
+        self.history.iter().zip(self.history.iter().skip(1))
+            .map(|((prev, _), (next, _))| *next - *prev)
+            .collect()
+    }
+}
+
+// the area the HUD covers, in the same coordinates it's drawn in
+pub fn hud_bounds() -> Rect {
+    Rect::from_xywh(5.0, 5.0, HISTORY as f32 + 10.0, 80.0)
+}
+
+pub fn draw_hud(canvas:&mut Canvas, stats:&FrameStats, backend:&str, context_id:usize){
+    let font = Font::new(Typeface::default(), 11.0);
+    let text = Paint::new(Color::WHITE.into(), None);
+    let bar = Paint::new(Color::from_rgb(0, 255, 128).into(), None);
+    let slow = Paint::new(Color::from_rgb(255, 64, 64).into(), None);
+    let mut backdrop = Paint::new(Color::BLACK.into(), None);
+    backdrop.set_alpha_f(0.6);
+
+    canvas.draw_rect(hud_bounds(), &backdrop);
+
+    let avg = stats.average();
+    let ms = |d:Duration| d.as_secs_f64() * 1000.0;
+    let losses = match stats.context_losses {
+        0 => String::new(),
+        n => format!(" · {} lost", n)
+    };
+    canvas.draw_str(format!("{} · context {}{}", backend, context_id, losses), (10.0, 18.0), &font, &text);
+    let gpu = match stats.gpu_time() {
+        Some(elapsed) => format!("  gpu {:.2}", ms(elapsed)),
+        None => String::new()
+    };
+    canvas.draw_str(format!("{:.1} fps  {:.2} ms{}", stats.fps(), ms(avg.total()), gpu), (10.0, 31.0), &font, &text);
+    canvas.draw_str(
+        format!("rec {:.2} flush {:.2} swap {:.2}", ms(avg.record), ms(avg.flush), ms(avg.present)),
+        (10.0, 44.0), &font, &text
+    );
+
+    // one bar per frame, scaled so that a 60fps frame is half the graph's height
+    let (graph_top, graph_bottom) = (50.0, 80.0);
+    let budget = 1000.0 / 60.0;
+    for (i, interval) in stats.frame_intervals().iter().enumerate() {
+        let t = ms(*interval) as f32;
+        let h = (t / budget * (graph_bottom - graph_top) / 2.0).min(graph_bottom - graph_top);
+        let x = 10.0 + i as f32;
+        canvas.draw_rect(
+            Rect::new(x, graph_bottom - h, x + 1.0, graph_bottom),
+            if t > budget * 1.5 { &slow } else { &bar }
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::sync::Mutex;
+use skia_safe::{Canvas, Color, FontMgr, Paint, Rect};
+use skia_safe::textlayout::{
+    FontCollection, Paragraph, ParagraphBuilder, ParagraphStyle, TextAlign, TextStyle,
+    TypefaceFontProvider
+};
+
+// Fonts added via `use_fonts` or `use_font_data`. Skia's typefaces can't be shared across
+// threads, so the library holds onto the raw font data and each render thread builds its
+// own TypefaceFontProvider from it (rebuilding whenever the generation count changes)
+struct FontLibrary {
+    generation: usize,
+    fonts: Vec<(Vec<u8>, Option<String>)>,
+    families: Vec<String>,
+}
+
+static FONT_LIBRARY: Mutex<FontLibrary> = Mutex::new(FontLibrary{
+    generation: 0, fonts: Vec::new(), families: Vec::new()
+});
+
+impl FontLibrary {
+    fn collection(&self) -> FontCollection {
+        let font_mgr = FontMgr::new();
+        let mut provider = TypefaceFontProvider::new();
+        for (data, alias) in &self.fonts {
+            for index in 0..face_count(data) {
+                if let Some(typeface) = font_mgr.new_from_data(data, index) {
+                    provider.register_typeface(typeface, alias.as_deref());
+                }
+            }
+        }
+
+        let mut collection = FontCollection::new();
+        collection.set_asset_font_manager(Some(provider.into()));
+        collection.set_default_font_manager(font_mgr, None);
+        collection
+    }
+}
+
+// TrueType/OpenType collections (.ttc/.otc) contain multiple faces, everything else just one
+fn face_count(data:&[u8]) -> usize {
+    match data.get(0..12) {
+        Some(header) if &header[0..4] == b"ttcf" => {
+            u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize
+        },
+        _ => 1
+    }
+}
+
+// Register in-memory font data (TTF, OTF, WOFF2, variable fonts, or collections) with every
+// window's text layout. If an alias is given, the faces are registered under that family name
+// rather than their own. Returns the family names that were added.
+pub fn use_font_data(data:&[u8], alias:Option<&str>) -> Result<Vec<String>, String> {
+    let font_mgr = FontMgr::new();
+    let mut families = vec![];
+    for index in 0..face_count(data) {
+        match font_mgr.new_from_data(data, index) {
+            Some(typeface) => families.push(match alias {
+                Some(alias) => alias.to_string(),
+                None => typeface.family_name()
+            }),
+            None => return Err(format!("Could not decode font data (face {})", index))
+        }
+    }
+    families.dedup();
+
+    let mut library = FONT_LIBRARY.lock().unwrap();
+    library.fonts.push((data.to_vec(), alias.map(|a| a.to_string())));
+    for family in &families {
+        if !library.families.contains(family) {
+            library.families.push(family.clone());
+        }
+    }
+    library.generation += 1;
+    Ok(families)
+}
+
+pub fn use_fonts(paths:&[impl AsRef<Path>], alias:Option<&str>) -> Result<Vec<String>, String> {
+    let mut families = vec![];
+    for path in paths {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let added = use_font_data(&data, alias).map_err(|e| format!("{}: {}", path.display(), e))?;
+        families.extend(added.into_iter().filter(|f| !families.contains(f)));
+    }
+    Ok(families)
+}
+
+// Family names of every font registered so far, in the order they were added
+pub fn families() -> Vec<String> {
+    FONT_LIBRARY.lock().unwrap().families.clone()
+}
+
+thread_local!{
+    // The collection caches typeface lookups and fallback matches, so every window
+    // rendering on a given thread shares the same one rather than re-resolving fonts
+    static FONT_COLLECTION: RefCell<Option<(usize, FontCollection)>> = RefCell::new(None);
+}
+
+pub fn font_collection() -> FontCollection {
+    let library = FONT_LIBRARY.lock().unwrap();
+    FONT_COLLECTION.with(|cached| {
+        let mut cached = cached.borrow_mut();
+        match cached.as_ref() {
+            Some((generation, collection)) if *generation == library.generation => collection.clone(),
+            _ => {
+                let collection = library.collection();
+                *cached = Some((library.generation, collection.clone()));
+                collection
+            }
+        }
+    })
+}
+
+fn font_generation() -> usize {
+    FONT_LIBRARY.lock().unwrap().generation
+}
+
+pub fn paragraph(text:&str, style:&TextStyle, align:TextAlign, width:f32) -> Paragraph {
+    let mut paragraph_style = ParagraphStyle::new();
+    paragraph_style.set_text_align(align);
+
+    let mut builder = ParagraphBuilder::new(&paragraph_style, font_collection());
+    builder.push_style(style);
+    builder.add_text(text);
+
+    let mut paragraph = builder.build();
+    paragraph.layout(width);
+    paragraph
+}
+
+// A run of text that's only re-laid-out when the width it's being wrapped to (or the set of
+// registered fonts) changes.
+// Coordinates are in logical pixels; scale the canvas by the window's scale factor before
+// drawing so glyphs get rasterized at the display's actual resolution.
+pub struct TextBlock {
+    text: String,
+    style: TextStyle,
+    layout: Option<(f32, usize, Paragraph)>,
+}
+
+impl TextBlock {
+    pub fn new(text:&str, size:f32, color:Color) -> Self {
+        let mut style = TextStyle::new();
+        style.set_font_size(size);
+        style.set_color(color);
+        TextBlock{ text: text.to_string(), style, layout: None }
+    }
+
+    pub fn with_families(mut self, families:&[impl AsRef<str>]) -> Self {
+        self.style.set_font_families(families);
+        self.layout = None;
+        self
+    }
+
+    pub fn paragraph(&mut self, width:f32) -> &Paragraph {
+        let generation = font_generation();
+        if !matches!(self.layout, Some((w, g, _)) if w == width && g == generation) {
+            let paragraph = paragraph(&self.text, &self.style, TextAlign::Left, width);
+            self.layout = Some((width, generation, paragraph));
+        }
+        &self.layout.as_ref().unwrap().2
+    }
+}
+
+pub const DEMO_TEXT: &str = "\
+Skia Canvas — The quick brown fox jumps over the lazy dog.
+日本語の文章、中文文本、한국어 텍스트
+مرحبا بالعالم — نص عربي من اليمين إلى اليسار
+😀 🎨 🚀 🦀 👩🏽‍💻";
+
+// prefers any fonts that have been registered, falling back to the system's for other scripts
+pub fn demo_text() -> TextBlock {
+    let block = TextBlock::new(DEMO_TEXT, 18.0, Color::BLACK);
+    match families() {
+        families if families.is_empty() => block,
+        families => block.with_families(&families)
+    }
+}
+
+// draw the block on a translucent panel along the bottom edge of a window (in logical pixels)
+pub fn draw_demo(canvas:&mut Canvas, block:&mut TextBlock, (width, height):(f32, f32)){
+    let inset = 20.0;
+    let paragraph = block.paragraph(width - inset * 2.0);
+    let top = height - paragraph.height() - inset;
+
+    let mut backdrop = Paint::new(Color::WHITE.into(), None);
+    backdrop.set_alpha_f(0.85);
+    canvas.draw_rect(Rect::from_xywh(inset / 2.0, top - inset / 2.0, width - inset, paragraph.height() + inset), &backdrop);
+    paragraph.paint(canvas, (inset, top));
+}
@@ -0,0 +1,20 @@
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::prelude::*;
+
+// TRACE=<path> records the stages of every frame as a Chrome trace (which can be opened in
+// chrome://tracing or ui.perfetto.dev). The file is written out when the guard is dropped, so
+// hold on to it until the event loop is done.
+pub fn init() -> Option<FlushGuard> {
+    let path = std::env::var_os("TRACE")?;
+    let (layer, guard) = ChromeLayerBuilder::new()
+        .file(path)
+        .include_args(true) // ← keep each span's window id and frame number
+        .build();
+
+    // installed directly rather than with `init()`, which would also try to take over from
+    // whatever `log` logger is already in place
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))
+        .map_err(|e| eprintln!("Could not start tracing: {}", e))
+        .ok()?;
+    Some(guard)
+}
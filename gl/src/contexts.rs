@@ -1,7 +1,8 @@
 use takeable_option::Takeable;
 
 use glutin::{
-    PossiblyCurrent, ContextError, NotCurrent, WindowedContext
+    event_loop::EventLoopWindowTarget, window::WindowBuilder,
+    ContextBuilder, CreationError, PossiblyCurrent, ContextError, NotCurrent, WindowedContext
 };
 
 pub enum ContextCurrentWrapper {
@@ -10,6 +11,13 @@ pub enum ContextCurrentWrapper {
 }
 
 impl ContextCurrentWrapper {
+    pub fn window(&self) -> &glutin::window::Window {
+        match self {
+            ContextCurrentWrapper::PossiblyCurrent(ctx) => ctx.window(),
+            ContextCurrentWrapper::NotCurrent(ctx) => ctx.window(),
+        }
+    }
+
     fn map_possibly<F>(self, f: F) -> Result<Self, (Self, ContextError)>
     where
         F: FnOnce(
@@ -76,6 +84,31 @@ impl ContextTracker {
         id
     }
 
+    // Build a context that shares textures, buffers, etc. with the ones already being tracked
+    // (the first context simply starts a new share group)
+    pub fn build_shared(
+        &self,
+        cb: ContextBuilder<NotCurrent>,
+        wb: WindowBuilder,
+        el: &EventLoopWindowTarget<()>,
+    ) -> Result<WindowedContext<NotCurrent>, CreationError> {
+        match self.others.first().map(|(_, ctx)| &**ctx) {
+            Some(ContextCurrentWrapper::PossiblyCurrent(ctx)) => cb.with_shared_lists(ctx.context()).build_windowed(wb, el),
+            Some(ContextCurrentWrapper::NotCurrent(ctx)) => cb.with_shared_lists(ctx.context()).build_windowed(wb, el),
+            None => cb.build_windowed(wb, el),
+        }
+    }
+
+    // Make sure *some* context is current, for work that any member of the share group can do
+    pub fn any_current(&mut self) -> Option<ContextId> {
+        let id = self.current.or_else(|| self.others.first().map(|(id, _)| *id))?;
+        self.get_current(id).ok().map(|_| id)
+    }
+
+    pub fn contains(&self, id: ContextId) -> bool {
+        self.others.binary_search_by(|(sid, _)| sid.cmp(&id)).is_ok()
+    }
+
     pub fn remove(&mut self, id: ContextId) -> ContextCurrentWrapper {
         if Some(id) == self.current {
             self.current.take();
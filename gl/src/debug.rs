@@ -0,0 +1,94 @@
+use std::os::raw::c_void;
+use std::sync::Mutex;
+use gl::types::*;
+use glutin::window::WindowId;
+use log::Level;
+
+use crate::contexts::ContextId;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugMode {
+    Log,    // ← forward every message to the log
+    Strict, // ← also panic at the end of any frame that raised a GL error
+}
+
+impl std::str::FromStr for DebugMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "" | "1" | "on" | "log" => Ok(DebugMode::Log),
+            "strict" => Ok(DebugMode::Strict),
+            _ => Err(format!("Unknown debug mode: {:?}", s))
+        }
+    }
+}
+
+// Passed to the driver along with the callback so each message can be attributed to the window
+// whose context raised it. It has to stay put (and alive) for as long as that context does.
+pub struct DebugTag {
+    window: WindowId,
+    context: ContextId,
+    mode: DebugMode,
+}
+
+// the first error raised under DebugMode::Strict, waiting for `check` to report it
+static FAILURE: Mutex<Option<String>> = Mutex::new(None);
+
+// Install the debug callback on the current context (which must have been created with the
+// debug flag set for drivers to report much of anything)
+pub unsafe fn install(window:WindowId, context:ContextId, mode:DebugMode) -> Result<Box<DebugTag>, String> {
+    if !gl::DebugMessageCallback::is_loaded() {
+        return Err("This context doesn't support KHR_debug".to_string())
+    }
+
+    let tag = Box::new(DebugTag{ window, context, mode });
+    gl::Enable(gl::DEBUG_OUTPUT);
+    gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS); // ← report from within the offending call
+    gl::DebugMessageCallback(Some(callback), &*tag as *const DebugTag as *const c_void);
+    gl::DebugMessageControl(gl::DONT_CARE, gl::DONT_CARE, gl::DONT_CARE, 0, std::ptr::null(), gl::TRUE);
+    Ok(tag)
+}
+
+// Panicking inside the callback would mean unwinding through the driver, so strict mode saves
+// the error and it's raised here instead, once the frame is done
+pub fn check(){
+    if let Some(msg) = FAILURE.lock().unwrap().take() {
+        panic!("GL error: {}", msg);
+    }
+}
+
+extern "system" fn callback(source:GLenum, kind:GLenum, id:GLuint, severity:GLenum, length:GLsizei, message:*const GLchar, tag:*mut c_void){
+    let tag = unsafe { &*(tag as *const DebugTag) };
+    let message = unsafe { std::slice::from_raw_parts(message as *const u8, length.max(0) as usize) };
+    let message = String::from_utf8_lossy(message);
+
+    let level = match (kind, severity) {
+        (gl::DEBUG_TYPE_ERROR, _) | (_, gl::DEBUG_SEVERITY_HIGH) => Level::Error,
+        (_, gl::DEBUG_SEVERITY_MEDIUM) => Level::Warn,
+        (_, gl::DEBUG_SEVERITY_LOW) => Level::Info,
+        _ => Level::Debug,
+    };
+    let source = match source {
+        gl::DEBUG_SOURCE_API => "api",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM => "window system",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY => "third party",
+        gl::DEBUG_SOURCE_APPLICATION => "application",
+        _ => "other",
+    };
+    let kind_name = match kind {
+        gl::DEBUG_TYPE_ERROR => "error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behavior",
+        gl::DEBUG_TYPE_PORTABILITY => "portability",
+        gl::DEBUG_TYPE_PERFORMANCE => "performance",
+        _ => "other",
+    };
+
+    let text = format!("[{:?} context {}] {} {} {:#x}: {}", tag.window, tag.context, source, kind_name, id, message.trim_end());
+    log::log!(target: "gl", level, "{}", text);
+
+    if tag.mode == DebugMode::Strict && kind == gl::DEBUG_TYPE_ERROR {
+        FAILURE.lock().unwrap().get_or_insert(text);
+    }
+}
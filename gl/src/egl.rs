@@ -0,0 +1,36 @@
+use std::ffi::c_void;
+use glutin::{platform::ContextTraitExt, window::Window, ContextWrapper, PossiblyCurrent};
+
+// The few EGL calls glutin doesn't make on our behalf, looked up through its loader (Mesa and
+// NVIDIA both hand out core entry points from eglGetProcAddress)
+type EGLDisplay = *const c_void;
+type EGLSurface = *const c_void;
+type GetCurrentSurface = unsafe extern "system" fn(readdraw:i32) -> EGLSurface;
+type QuerySurface = unsafe extern "system" fn(display:EGLDisplay, surface:EGLSurface, attribute:i32, value:*mut i32) -> u32;
+
+const EGL_DRAW: i32 = 0x3059;
+const EGL_BUFFER_AGE_EXT: i32 = 0x313D;
+
+// How many swaps ago the current context's back buffer was last drawn into, or 0 if its contents
+// are undefined (as they always are without EGL_EXT_buffer_age, or on GLX and WGL)
+pub fn buffer_age(win:&ContextWrapper<PossiblyCurrent, Window>) -> usize {
+    let display = match unsafe { win.get_egl_display() } {
+        Some(display) => display,
+        None => return 0
+    };
+    let (get_surface, query) = (win.get_proc_address("eglGetCurrentSurface"), win.get_proc_address("eglQuerySurface"));
+    if get_surface.is_null() || query.is_null() {
+        return 0
+    }
+
+    let mut age = 0;
+    unsafe {
+        let get_surface:GetCurrentSurface = std::mem::transmute(get_surface);
+        let query:QuerySurface = std::mem::transmute(query);
+        let surface = get_surface(EGL_DRAW);
+        if surface.is_null() || query(display, surface, EGL_BUFFER_AGE_EXT, &mut age) == 0 {
+            return 0 // ← EGL_BAD_ATTRIBUTE if the extension isn't there
+        }
+    }
+    age.max(0) as usize
+}
@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use gl::types::*;
+
+// how many frames can be in flight before timing starts skipping them
+const MAX_PENDING: usize = 4;
+
+// Measures how long the GPU spends on each frame's flush with GL_TIME_ELAPSED queries. Results
+// only become available once the GPU has caught up, so they're collected a few frames later.
+//
+// Like PboReadback, all of the methods must be called with the owning window's context current.
+#[derive(Default)]
+pub struct GpuTimer {
+    pending: VecDeque<GLuint>,
+    free: Vec<GLuint>,
+    active: Option<GLuint>,
+}
+
+impl GpuTimer {
+    // Start timing the GPU work issued from here until `end`. Only one timer query can be active
+    // at a time, and frames are skipped while too many earlier ones are still waiting on the GPU.
+    pub unsafe fn begin(&mut self){
+        if self.active.is_some() || self.pending.len() >= MAX_PENDING {
+            return
+        }
+        let query = self.free.pop().unwrap_or_else(|| {
+            let mut query = 0;
+            gl::GenQueries(1, &mut query);
+            query
+        });
+        gl::BeginQuery(gl::TIME_ELAPSED, query);
+        self.active = Some(query);
+    }
+
+    pub unsafe fn end(&mut self){
+        if let Some(query) = self.active.take() {
+            gl::EndQuery(gl::TIME_ELAPSED);
+            self.pending.push_back(query);
+        }
+    }
+
+    // Collect the timings of any frames the GPU has finished (oldest first)
+    pub unsafe fn poll(&mut self) -> Vec<Duration> {
+        let mut elapsed = vec![];
+        while let Some(&query) = self.pending.front() {
+            let mut available: GLuint = 0;
+            gl::GetQueryObjectuiv(query, gl::QUERY_RESULT_AVAILABLE, &mut available);
+            if available == 0 {
+                break // ← queries complete in order, so the rest aren't ready either
+            }
+            let mut nanos: GLuint64 = 0;
+            gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut nanos);
+            elapsed.push(Duration::from_nanos(nanos));
+            self.free.push(self.pending.pop_front().unwrap());
+        }
+        elapsed
+    }
+
+    pub unsafe fn destroy(&mut self){
+        let queries:Vec<GLuint> = self.pending.drain(..).chain(self.free.drain(..)).chain(self.active.take()).collect();
+        if !queries.is_empty() {
+            gl::DeleteQueries(queries.len() as GLsizei, queries.as_ptr());
+        }
+    }
+
+    // Forget about the queries when their context is gone (and took them with it)
+    pub fn abandon(&mut self){
+        self.pending.clear();
+        self.free.clear();
+        self.active = None;
+    }
+}
@@ -1,65 +1,281 @@
 use std::time::{Duration, Instant};
 use std::cell::RefCell;
 use std::rc::{Rc};
+use std::sync::Arc;
+use std::collections::HashMap;
 use gl::{self, types::*};
 
 mod contexts;
-use contexts::ContextTracker;
+use contexts::{ContextId, ContextTracker};
+
+#[path = "../../common/stats.rs"]
+mod stats;
+use stats::{FrameStats, FrameTiming};
+
+#[path = "../../common/text.rs"]
+mod text;
+use text::TextBlock;
+
+#[path = "../../common/capture.rs"]
+mod capture;
+
+mod readback;
+use readback::{PboReadback, ReadbackCallback};
+
+#[path = "../../common/recording.rs"]
+mod recording;
+use recording::{Recorder, RecordingFormat};
+
+mod shared;
+use shared::SharedImage;
+
+#[path = "../../common/memory.rs"]
+mod memory;
+use memory::{BudgetShare, MemoryReport, MemoryUsage};
+
+mod debug;
+use debug::{DebugMode, DebugTag};
+
+#[path = "../../common/trace.rs"]
+mod trace;
+
+mod gpu_timer;
+use gpu_timer::GpuTimer;
+
+#[path = "../../common/shader_cache.rs"]
+mod shader_cache;
+use shader_cache::ProgramCache;
+
+#[path = "../../common/ready.rs"]
+mod ready;
+
+#[path = "../../common/gpu_options.rs"]
+mod gpu_options;
+use gpu_options::GpuOptions;
+
+#[path = "../../common/picture.rs"]
+mod picture;
+use picture::FrameRecorder;
+
+#[path = "../../common/export.rs"]
+mod export;
+use export::ExportFormat;
+
+#[path = "../../common/damage.rs"]
+mod damage;
+use damage::{Damage, DamageMode, DamageTracker};
+
+#[path = "../../common/env.rs"]
+mod env;
+
+#[path = "../../common/fit.rs"]
+mod fit;
+use fit::CanvasFit;
+
+mod egl;
+
+mod swap_interval;
 
 use glutin::{
-    dpi::{LogicalSize, PhysicalSize},
-    event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
-    GlProfile, PossiblyCurrent
+    dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
+    window::{Window, WindowBuilder, WindowId},
+    GlProfile, PixelFormat, PossiblyCurrent, Robustness
 };
 
 use skia_safe::{
-    gpu::{gl::FramebufferInfo, BackendRenderTarget, DirectContext, SurfaceOrigin},
-    Color, ColorType, Surface, HSV, Color4f, Paint, Point, Rect, Size,
+    gpu::{gl::{Format, FramebufferInfo}, BackendRenderTarget, DirectContext, SurfaceOrigin},
+    Canvas, Color, ColorType, Data, EncodedImageFormat, Image, Surface, HSV, Color4f, Paint, Point, Rect, Size,
 };
 
+// Pick the Skia format & color type that match the pixel format the driver actually handed
+// us (which isn't necessarily the one we asked for in the ContextBuilder). An sRGB-capable
+// config only counts if we asked for sRGB, since GL_FRAMEBUFFER_SRGB stays off otherwise
+fn framebuffer_format(pixel_format:&PixelFormat, srgb:bool) -> Result<(Format, ColorType), String> {
+    match (pixel_format.color_bits, pixel_format.alpha_bits, pixel_format.srgb && srgb) {
+        (24, 8, true) => Ok((Format::SRGB8_ALPHA8, ColorType::SRGBA8888)),
+        (24, 8, false) => Ok((Format::RGBA8, ColorType::RGBA8888)),
+        (30, 2, _) => Ok((Format::RGB10_A2, ColorType::RGBA1010102)),
+        (16, 0, _) => Ok((Format::RGB565, ColorType::RGB565)),
+        (color, alpha, srgb) => Err(format!(
+            "Unsupported framebuffer pixel format: {} color bits, {} alpha bits{}",
+            color, alpha, if srgb { " (sRGB)" } else { "" }
+        ))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PresentMode {
+    Fifo,
+    Mailbox,
+    Immediate,
+    Adaptive,
+}
+
+impl std::str::FromStr for PresentMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fifo" | "vsync" => Ok(PresentMode::Fifo),
+            "mailbox" => Ok(PresentMode::Mailbox),
+            "immediate" => Ok(PresentMode::Immediate),
+            "adaptive" => Ok(PresentMode::Adaptive),
+            _ => Err(format!("Unknown present mode: {:?}", s))
+        }
+    }
+}
+
+impl PresentMode {
+    // the swap interval can only turn vsync on or off, so Mailbox degrades to Immediate and
+    // Adaptive to Fifo
+    fn effective(&self) -> Self {
+        match self {
+            PresentMode::Fifo | PresentMode::Adaptive => PresentMode::Fifo,
+            PresentMode::Mailbox | PresentMode::Immediate => PresentMode::Immediate,
+        }
+    }
+}
+
+// What a window's context has lost, if anything
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContextLoss {
+    Abandoned, // Skia gave up on its context, but the GL context itself is still usable
+    Reset,     // the driver reset the GL context (and with it everything in the share group)
+}
+
+// The driver behind the current context, which Skia's program cache is keyed by
+unsafe fn gl_driver() -> String {
+    let string = |name| match gl::GetString(name) {
+        ptr if ptr.is_null() => String::new(),
+        ptr => std::ffi::CStr::from_ptr(ptr as *const _).to_string_lossy().into_owned()
+    };
+    format!("{} {} {}", string(gl::VENDOR), string(gl::RENDERER), string(gl::VERSION))
+}
+
+// Windows render into a plain RGBA8 framebuffer, not an sRGB one
+const SRGB:bool = false;
+
+// Create a window along with a context in the share group and make it current (with a debug
+// callback installed if `debug` is set)
+fn build_context(el:&EventLoopWindowTarget<()>, ct:&Rc<RefCell<ContextTracker>>, wb:WindowBuilder, present_mode:PresentMode, debug:Option<DebugMode>) -> Result<(ContextId, (Format, ColorType), Option<Box<DebugTag>>), String> {
+    let cb = glutin::ContextBuilder::new()
+        .with_vsync(present_mode == PresentMode::Fifo)
+        .with_depth_buffer(0)
+        .with_stencil_buffer(8)
+        .with_pixel_format(24, 8)
+        .with_srgb(SRGB) // ← glutin asks for sRGB by default
+        .with_gl_profile(GlProfile::Core)
+        .with_gl_robustness(Robustness::TryRobustLoseContextOnReset)
+        .with_gl_debug_flag(debug.is_some());
+
+    #[cfg(not(feature = "wayland"))]
+    let cb = cb.with_double_buffer(Some(true));
+
+    // every window joins the same share group so SharedImages can be drawn in any of them
+    let windowed_context = ct.borrow().build_shared(cb, wb, el).map_err(|e| e.to_string())?;
+    let windowed_context = unsafe { windowed_context.make_current().map_err(|(_, e)| format!("{:?}", e))? };
+    gl::load_with(|s| windowed_context.get_proc_address(s));
+
+    let format = framebuffer_format(&windowed_context.get_pixel_format(), SRGB)?;
+    let window_id = windowed_context.window().id();
+    let id = ct.borrow_mut().insert(windowed_context);
+    let tag = debug.and_then(|mode| match unsafe { debug::install(window_id, id, mode) } {
+        Ok(tag) => Some(tag),
+        Err(msg) => {
+            log::warn!("No GL debug output for context {}: {}", id, msg);
+            None
+        }
+    });
+    Ok((id, format, tag))
+}
+
 struct GLWindow {
     _id: usize,
     _ct: Rc<RefCell<ContextTracker>>,
     _surface: Option<Surface>,
+    // this context's handle on a shared image & the image itself. The handle doesn't own the
+    // texture it wraps, so it comes first in the tuple to be dropped before the SharedImage
+    overlay: Option<(Image, Rc<SharedImage>)>,
     sk_context: DirectContext, // ← must be dropped before the WindowedContext!
+    _program_cache: Option<Arc<ProgramCache>>, // ← and before the shader cache it was given
+    format: (Format, ColorType),
+    present_mode: PresentMode,
+    stats: FrameStats,
+    timing: FrameTiming,
+    show_hud: bool,
+    text: Option<TextBlock>,
+    fboid: GLuint,
+    readback: PboReadback,
+    gpu_timer: GpuTimer,
+    readback_requests: Vec<(f32, ReadbackCallback)>,
+    recorder: Option<Rc<RefCell<Recorder>>>,
+    pictures: FrameRecorder,
+    record_pictures: bool,   // ← record every frame as a picture before drawing it
+    picture_requested: bool, // ← record the next frame and save it
+    damage: DamageTracker, // ← holds the last frame, redrawing only what the scene reports as changed
+    present_damage: Option<Vec<glutin::Rect>>, // ← what changed in the frame waiting to be swapped
+    drawn: HSV, // ← the color the last frame was drawn with
+    fit: Option<CanvasFit>, // ← draw to a fixed-size canvas that's then scaled into the window
+    pointer: Option<Point>, // ← where the pointer is on that canvas
+    budget: BudgetShare,
+    lost: bool, // ← set when a swap fails
+    created: Option<Instant>, // ← cleared once the first frame has been presented
+    debug: Option<DebugMode>,
+    gpu_options: GpuOptions,
+    _debug_tag: Option<Box<DebugTag>>, // ← must outlive the context it was installed in
     color: HSV
 }
 
 impl GLWindow {
-    pub fn new(el:&EventLoop<()>, ct:&mut Rc<RefCell<ContextTracker>>) -> Self {
+    pub fn new(el:&EventLoopWindowTarget<()>, ct:&mut Rc<RefCell<ContextTracker>>, present_mode:PresentMode, debug:Option<DebugMode>, gpu_options:&GpuOptions) -> Result<Self, String> {
         let size:LogicalSize<i32> = LogicalSize::new(400, 300);
 
         let wb = WindowBuilder::new()
             .with_inner_size(size)
             .with_title("GL Window");
-        let cb = glutin::ContextBuilder::new()
-            .with_depth_buffer(0)
-            .with_stencil_buffer(8)
-            .with_pixel_format(24, 8)
-            .with_gl_profile(GlProfile::Core);
-
-        #[cfg(not(feature = "wayland"))]
-        let cb = cb.with_double_buffer(Some(true));
-
-        let windowed_context = cb.build_windowed(wb, &el).unwrap(); // ← this should be the safe bail-out point
-        let windowed_context = unsafe { windowed_context.make_current().unwrap() };
-        gl::load_with(|s| windowed_context.get_proc_address(s));
-
-        let sk_context = skia_safe::gpu::DirectContext::new_gl(None, None).unwrap();
+        let present_mode = present_mode.effective();
+        let (_id, format, _debug_tag) = build_context(el, ct, wb, present_mode, debug)?;
+        let mut options = gpu_options.context_options();
+        let program_cache = shader_cache::attach(&mut options, &unsafe { gl_driver() });
+        let sk_context = skia_safe::gpu::DirectContext::new_gl(None, &options).unwrap();
         // let sf = windowed_context.window().scale_factor() as f32;
         // surface.canvas().scale((sf, sf));
 
-        let _id = ct.borrow_mut().insert(windowed_context);
-
-        GLWindow {
+        Ok(GLWindow {
             _id,
             _ct: Rc::clone(&ct),
             _surface: None,
+            overlay: None,
             sk_context,
+            _program_cache: program_cache,
+            format,
+            present_mode,
+            stats: FrameStats::default(),
+            timing: FrameTiming::default(),
+            show_hud: std::env::var("HUD").is_ok(),
+            text: std::env::var("TEXT").ok().map(|_| text::demo_text()),
+            fboid: 0,
+            readback: PboReadback::default(),
+            gpu_timer: GpuTimer::default(),
+            readback_requests: vec![],
+            recorder: None,
+            pictures: FrameRecorder::new(),
+            record_pictures: std::env::var("PICTURES").is_ok(),
+            picture_requested: false,
+            damage: DamageTracker::new(env::parse("DAMAGE").unwrap_or(DamageMode::Full)),
+            present_damage: None,
+            drawn: HSV::from((0.5, 1.0, 0.3)),
+            fit: CanvasFit::from_env(),
+            pointer: None,
+            budget: BudgetShare::new(),
+            lost: false,
+            created: Some(Instant::now()),
+            debug,
+            gpu_options: gpu_options.clone(),
+            _debug_tag,
             color: HSV::from((0.5, 1.0, 0.3))
-        }
+        })
     }
     pub fn window_id<'a>(&'a self) -> glutin::window::WindowId {
         let ct = &mut self._ct.borrow_mut();
@@ -67,6 +283,75 @@ impl GLWindow {
         windowed_context.window().id()
     }
 
+    pub fn present_mode(&self) -> PresentMode {
+        self.present_mode
+    }
+
+    // Switch vsync on or off for a context that's already been created
+    pub fn set_present_mode(&mut self, present_mode:PresentMode) -> Result<(), String> {
+        let present_mode = present_mode.effective();
+        let ct = &mut self._ct.borrow_mut();
+        let win = ct.get_current(self._id).ok_or_else(|| "Window has no context".to_string())?;
+        swap_interval::set(win, if present_mode == PresentMode::Fifo { 1 } else { 0 })?;
+        self.present_mode = present_mode;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    pub fn toggle_hud(&mut self){
+        self.show_hud = !self.show_hud;
+        self.invalidate();
+    }
+
+    pub fn toggle_text(&mut self){
+        self.text = match self.text {
+            Some(_) => None,
+            None => Some(text::demo_text())
+        };
+        self.invalidate();
+    }
+
+    // switch to the next way of fitting the canvas into the window (if it has a fixed-size one)
+    pub fn cycle_fit(&mut self){
+        if let Some(fit) = self.fit.as_mut() {
+            println!("Context {} fits its canvas with {:?}", self._id, fit.cycle_mode());
+        }
+        self.move_pointer(None); // ← the same spot in the window is somewhere else on the canvas now
+    }
+
+    // Follow the pointer (given in window pixels) across the fixed-size canvas
+    pub fn move_pointer(&mut self, position:Option<PhysicalPosition<f64>>){
+        let (size, sf) = (self.inner_size(), self.scale_factor() as f32);
+        let pointer = self.fit.as_ref().zip(position).and_then(|(fit, position)| {
+            let window = (size.width as f32, size.height as f32);
+            fit.to_canvas(Point::new(position.x as f32, position.y as f32), window, sf)
+        });
+        if pointer != self.pointer {
+            self.pointer = pointer;
+            self.invalidate();
+        }
+    }
+
+    // redraw the next frame in full, since something the scene doesn't track has changed
+    fn invalidate(&mut self){
+        self.damage.invalidate();
+    }
+
+    pub fn scale_factor(&self) -> f64 {
+        let ct = &mut self._ct.borrow_mut();
+        let windowed_context = ct.get_current(self._id).unwrap();
+        windowed_context.window().scale_factor()
+    }
+
+    pub fn inner_size(&self) -> PhysicalSize<u32> {
+        let ct = &mut self._ct.borrow_mut();
+        let windowed_context = ct.get_current(self._id).unwrap();
+        windowed_context.window().inner_size()
+    }
+
     pub fn with_gl_win<F>(&self, f:F)
         where F:Fn(&mut glutin::ContextWrapper<PossiblyCurrent, Window>)
     {
@@ -77,56 +362,440 @@ impl GLWindow {
 
     pub fn resize(&mut self, size: PhysicalSize<u32>){
         self.with_gl_win(|win| win.resize(size));
+        if self._surface.take().is_some() {
+            self.stats.surface_recreations += 1;
+        }
+        if let Some((width, height)) = self.fit.as_mut().and_then(|fit| fit.lock_aspect((size.width, size.height))) {
+            self.with_gl_win(|win| win.window().set_inner_size(PhysicalSize::new(width, height)));
+        }
+        // minimized windows are resized to nothing; nothing they've cached is needed until they come back
+        if size.width == 0 || size.height == 0 {
+            self.purge();
+        }
+    }
+
+    // Release every cached GPU resource that isn't currently in use
+    pub fn purge(&mut self){
+        self.with_gl_win(|_| ());
+        self.sk_context.purge_unlocked_resources(None, false);
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::of(&self.sk_context)
+    }
+
+    pub fn context_loss(&mut self) -> Option<ContextLoss> {
+        if self.lost || self.graphics_reset() {
+            Some(ContextLoss::Reset)
+        } else if self.sk_context.abandoned() {
+            Some(ContextLoss::Abandoned)
+        } else {
+            None
+        }
+    }
+
+    // Robust contexts report driver resets rather than crashing; without robustness (or before
+    // GL 4.5) the best we can do is notice that the context can't be made current any more
+    fn graphics_reset(&self) -> bool {
+        if self._ct.borrow_mut().get_current(self._id).is_err() {
+            return true
+        }
+        unsafe { gl::GetGraphicsResetStatus::is_loaded() && gl::GetGraphicsResetStatus() != gl::NO_ERROR }
+    }
+
+    // Simulate losing the context, to exercise `restore`
+    pub fn abandon_context(&mut self){
+        self.sk_context.abandon();
+    }
+
+    // Rebuild whatever was lost so the window can keep drawing. A reset GL context can't be
+    // revived, so in that case the OS window is replaced by a new one (with a new WindowId) in the
+    // same spot. Either way, everything the window had on the GPU is gone, including its handle on
+    // any shared image, and it's up to the caller to upload it again.
+    pub fn restore(&mut self, el:&EventLoopWindowTarget<()>, loss:ContextLoss) -> Result<(), String> {
+        self.sk_context.abandon();
         self._surface = None;
+        self.overlay = None;
+        self.readback.abandon();
+        self.gpu_timer.abandon();
+        self.damage.abandon();
+        if let Some(fit) = self.fit.as_mut() {
+            fit.abandon();
+        }
+
+        if loss == ContextLoss::Reset {
+            let old = self._ct.borrow_mut().remove(self._id);
+            let mut wb = WindowBuilder::new()
+                .with_inner_size(old.window().inner_size())
+                .with_title("GL Window");
+            if let Ok(position) = old.window().outer_position() {
+                wb = wb.with_position(position);
+            }
+            drop(old);
+
+            let (id, format, debug_tag) = build_context(el, &self._ct, wb, self.present_mode, self.debug)?;
+            self._id = id;
+            self.format = format;
+            self._debug_tag = debug_tag;
+        } else {
+            self.with_gl_win(|_| ());
+        }
+
+        let mut options = self.gpu_options.context_options();
+        let program_cache = shader_cache::attach(&mut options, &unsafe { gl_driver() });
+        self.sk_context = DirectContext::new_gl(None, &options)
+            .ok_or_else(|| "Could not create a new Skia context".to_string())?;
+        self._program_cache = program_cache;
+        self.budget = BudgetShare::new();
+        self.lost = false;
+        self.created = Some(Instant::now());
+        self.stats.context_losses += 1;
+        Ok(())
     }
 
     pub fn request_redraw(&mut self){
+        let _span = tracing::info_span!("request_redraw", window = self._id).entered();
         self.with_gl_win(|win| win.window().request_redraw());
     }
 
+    // Show the last frame again without advancing the animation, for when the OS asks for a redraw
+    // (after the window is uncovered, say). The scene is only drawn again if there's no frame of
+    // the window's current size to show.
     pub fn redraw(&mut self){
+        if self.lost || self.sk_context.abandoned() {
+            return
+        }
+        let size = self.inner_size();
+        let retained = match self.fit.as_ref() {
+            Some(fit) => fit.has_frame(),
+            None => self.damage.has_frame((size.width as i32, size.height as i32))
+        };
+        if !retained {
+            self.render();
+            self.present();
+            return
+        }
+
+        let _span = tracing::info_span!("present_last", window = self._id).entered();
+        self.with_gl_win(|_| ());
+        let sf = self.scale_factor() as f32;
+        if self.surface().is_some() {
+            let canvas = self._surface.as_mut().unwrap().canvas();
+            match self.fit.as_mut() {
+                Some(fit) => fit.present(canvas, (size.width as f32, size.height as f32), sf),
+                None => self.damage.repeat(canvas)
+            }
+        }
+        self.sk_context.flush_and_submit();
+        self.present_damage = None;
+        self.swap_buffers();
+    }
+
+    // advance the animation and draw its next frame
+    pub fn update(&mut self){
         self.color.h += 1.0;
         self.color.h %= 360.0;
+        self.render();
+    }
 
-        let s = 200.0 - 100.0 * ((self.color.h/180.0 * std::f32::consts::PI).cos() / 2.0 + 0.5);
-        let mut x = (self.color.h/180.0 * std::f32::consts::PI).sin() / 2.0 + 0.5;
-        let color:Color4f = self.color.to_color(255).into();
+    // draw the current frame and flush it to the GPU without waiting on the swap
+    pub fn render(&mut self){
+        if self.lost || self.sk_context.abandoned() {
+            return // ← wait for the context to be restored
+        }
+        let _span = tracing::info_span!("render", window = self._id, frame = self.stats.frames).entered();
 
-        if let Some(surface) = self.surface(){
-            let canvas = surface.canvas();
-            canvas.clear(Color::WHITE);
+        if let Some(recorder) = &self.recorder {
+            let recorder = Rc::clone(recorder);
+            self.read_pixels_async(1.0, move |frame| {
+                if let Err(msg) = frame.and_then(|frame| recorder.borrow_mut().add_frame(&frame)) {
+                    eprintln!("Recording: {}", msg);
+                }
+            });
+        }
 
-            let canvas_size = Size::from(canvas.base_layer_size());
-            let rect_size = canvas_size * 0.9;
-            let rect = Rect::from_point_and_size(
-                Point::new(
-                    (canvas_size.width - rect_size.width) / 2.0,
-                    (canvas_size.height - rect_size.height) / 2.0,
-                ),
-                rect_size,
-            );
+        if !self.readback.is_idle() {
+            let _span = tracing::info_span!("readback_poll").entered();
+            self.with_gl_win(|_| ());
+            unsafe { self.readback.poll() };
+            self.sk_context.reset(None);
+        }
+
+        tracing::info_span!("make_current").in_scope(|| self.with_gl_win(|_| ()));
+        self.budget.apply(&mut self.sk_context);
+        for elapsed in unsafe { self.gpu_timer.poll() } {
+            self.stats.add_gpu_time(elapsed);
+        }
 
-            canvas.draw_rect(rect, &Paint::new(color, None));
+        let draw_span = tracing::info_span!("draw").entered();
+        let started = Instant::now();
+        let (prev, color) = (self.drawn, self.color);
+        let (size, sf) = self.canvas_size();
+        if self.record_pictures || self.picture_requested {
+            self.pictures.begin(size);
+        }
+        let mut damage = scene_damage(&prev, &color, (size.0 as f32, size.1 as f32));
+        if self.show_hud {
+            let hud = stats::hud_bounds();
+            damage.add(Rect::new(hud.left * sf, hud.top * sf, hud.right * sf, hud.bottom * sf));
+        }
+        if self.pictures.is_recording() {
+            self.damage.invalidate(); // ← pictures hold the whole frame, so it's drawn in full
+        }
+        self.damage.begin(&mut self.sk_context, size, 1.0, &mut damage);
 
-            let h = canvas.base_layer_size().height as f32;
-            let w = canvas.base_layer_size().width as f32;
-            x *= w;
-            let line = Rect::new(x-s/2.0, 0.0f32, x+s, h);
+        let mut text = self.text.take();
+        if let Some(canvas) = self.frame_canvas(){
+            let size = Size::from(canvas.base_layer_size());
+            draw_scene(canvas, &color, (size.width, size.height), sf, text.as_mut());
+        }
+        self.text = text;
 
-            let color:Color4f = Color::WHITE.into();
-            canvas.draw_rect(line, &Paint::new(color, None));
+        if let Some((image, _)) = self.overlay.clone() {
+            if let Some(canvas) = self.frame_canvas(){
+                shared::draw_picture_in_picture(canvas, &image);
+            }
+        }
 
+        if let Some(pointer) = self.pointer {
+            if let Some(canvas) = self.frame_canvas(){
+                fit::draw_pointer(canvas, pointer);
+            }
         }
 
-        self.sk_context.flush(None);
-        self.with_gl_win(|win| win.swap_buffers().unwrap());
+        if self.show_hud {
+            let (stats, id) = (self.stats.clone(), self._id);
+            if let Some(canvas) = self.frame_canvas(){
+                canvas.save();
+                canvas.scale((sf, sf));
+                stats::draw_hud(canvas, &stats, "OpenGL", id);
+                canvas.restore();
+            }
+        }
+
+        if let Some(picture) = self.pictures.finish() {
+            if let Some(canvas) = self.frame_canvas() {
+                canvas.draw_picture(&picture, None, None);
+            }
+            if std::mem::take(&mut self.picture_requested) {
+                report_picture(picture::save(&picture, "frame"));
+            }
+        }
+
+        // a fixed-size canvas always holds the last frame, while EGL can say which one a window
+        // buffer holds
+        let buffer_age = match self.fit {
+            Some(_) => 1,
+            None => self.buffer_age()
+        };
+        self.surface(); // ← make sure the window's has been created
+        let target = match self.fit.as_mut() {
+            Some(fit) => fit.surface(&mut self.sk_context),
+            None => self._surface.as_mut()
+        };
+        if let Some(surface) = target {
+            let changed = self.damage.finish(surface.canvas(), &damage, buffer_age);
+            self.present_damage = (!changed.is_full()).then(|| swap_damage(&changed, size));
+        }
+
+        // the canvas is scaled into the window (letterbox and all) afresh every frame
+        let mut fit = self.fit.take();
+        if let Some(fit) = fit.as_mut() {
+            let window = self.inner_size();
+            let sf = self.scale_factor() as f32;
+            if let Some(surface) = self.surface() {
+                fit.present(surface.canvas(), (window.width as f32, window.height as f32), sf);
+            }
+            self.present_damage = None;
+        }
+        self.fit = fit;
+        self.drawn = color;
+
+        drop(draw_span);
+        let recorded = Instant::now();
+        tracing::info_span!("flush").in_scope(|| unsafe {
+            self.gpu_timer.begin();
+            self.sk_context.flush(None);
+            self.gpu_timer.end();
+        });
+        self.timing = FrameTiming{
+            record: recorded - started,
+            flush: recorded.elapsed(),
+            present: Duration::ZERO
+        };
+
+        if !self.readback_requests.is_empty() {
+            let _span = tracing::info_span!("readback_request").entered();
+            let size = self.surface().map(|s| (s.width(), s.height())).unwrap_or_default();
+            for (scale, callback) in self.readback_requests.drain(..) {
+                unsafe { self.readback.request(self.fboid, size, scale, callback) };
+            }
+            self.sk_context.reset(None);
+        }
+    }
+
+    // Capture every frame from here on, writing them to files starting with `prefix`
+    pub fn start_recording(&mut self, format:RecordingFormat, prefix:&str){
+        self.recorder = Some(Rc::new(RefCell::new(Recorder::new(format, prefix))));
+    }
+
+    // The file gets written once the frames that are still being read back have arrived
+    pub fn stop_recording(&mut self){
+        self.recorder = None;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // Capture the next frame that gets rendered without stalling the pipeline. The pixels (shrunk
+    // by `scale` if it's less than 1.0) are delivered to the callback a frame or two later.
+    pub fn read_pixels_async<F>(&mut self, scale:f32, callback:F)
+        where F:FnOnce(Result<Image, String>) + 'static
+    {
+        self.readback_requests.push((scale, Box::new(callback)));
+    }
+
+    pub fn present(&mut self){
+        let _span = tracing::info_span!("swap_buffers", window = self._id, frame = self.stats.frames).entered();
+        let started = Instant::now();
+        self.swap_buffers();
+        self.timing.present = started.elapsed();
+        self.stats.add_frame(self.timing);
+
+        // most of the wait for the first frame is compiling shaders, which the cache cuts down on
+        if let Some(created) = self.created.take() {
+            println!("Context {} presented its first frame after {:.1} ms", self._id, created.elapsed().as_secs_f64() * 1000.0);
+            ready::first_frame_presented();
+        }
+
+        if self.debug == Some(DebugMode::Strict) {
+            debug::check();
+        }
+    }
+
+    fn swap_buffers(&mut self){
+        let swapped = {
+            let ct = &mut self._ct.borrow_mut();
+            let damage = self.present_damage.take();
+            ct.get_current(self._id).and_then(|win| match damage {
+                Some(rects) if win.swap_buffers_with_damage_supported() => win.swap_buffers_with_damage(&rects),
+                _ => win.swap_buffers()
+            })
+        };
+        if swapped.is_err() {
+            self.lost = true;
+        }
+    }
+
+    fn buffer_age(&self) -> usize {
+        let ct = &mut self._ct.borrow_mut();
+        ct.get_current(self._id).map_or(0, |win| egl::buffer_age(win))
+    }
+
+    // The back buffer's contents are undefined once it's been swapped, so render a fresh frame
+    // and read it back before presenting it
+    pub fn snapshot(&mut self) -> Result<Image, String> {
+        self.render();
+        let image = match self.surface() {
+            Some(surface) => capture::read_canvas(surface.canvas()),
+            None => Err("Window has no surface to read from".to_string())
+        };
+        self.present();
+        image
+    }
+
+    pub fn screenshot(&mut self, format:EncodedImageFormat, quality:u32) -> Result<Data, String> {
+        capture::encode(&self.snapshot()?, format, quality)
+    }
+
+    // Upload an image into the share group using this window's context; the result can then be
+    // handed to any window's `set_overlay`
+    pub fn share_image(&mut self, image:&Image) -> Result<SharedImage, String> {
+        self.with_gl_win(|_| ());
+        let shared = unsafe { SharedImage::upload(&self._ct, image) };
+        self.sk_context.reset(None);
+        shared
+    }
+
+    // Show a shared image as a picture-in-picture inset (or remove it by passing None)
+    pub fn set_overlay(&mut self, shared:Option<Rc<SharedImage>>){
+        self.with_gl_win(|_| ());
+        self.overlay = shared.and_then(|shared| {
+            let image = shared.image(&mut self.sk_context)?;
+            Some((image, shared))
+        });
+        self.invalidate();
+    }
+
+    pub fn has_overlay(&self) -> bool {
+        self.overlay.is_some()
+    }
+
+    pub fn overlay(&self) -> Option<Rc<SharedImage>> {
+        self.overlay.as_ref().map(|(_, shared)| Rc::clone(shared))
+    }
+
+    // Save the last frame that was recorded as a picture, or record the next one and save that
+    pub fn save_picture(&mut self){
+        match self.pictures.last() {
+            Some(picture) if self.record_pictures => report_picture(picture::save(picture, "frame")),
+            _ => self.picture_requested = true,
+        }
+    }
+
+    // Draw the window's current frame again with Skia's SVG or PDF backend, at its logical size
+    // (or the size of its fixed canvas, if it has one)
+    pub fn export_frame(&mut self, format:ExportFormat) -> Data {
+        let ((width, height), sf) = self.canvas_size();
+        let (width, height) = (width as f32, height as f32);
+        let (color, text) = (self.color, &mut self.text);
+        export::export(format, (width / sf, height / sf), |canvas| {
+            canvas.scale((1.0 / sf, 1.0 / sf)); // ← the scene itself is laid out in pixels
+            draw_scene(canvas, &color, (width, height), sf, text.as_mut());
+        })
+    }
+
+    // where the frame gets drawn: the picture being recorded if there is one, then the damage
+    // tracker's copy of the last frame, or else the fixed-size canvas or the window
+    fn frame_canvas(&mut self) -> Option<&mut Canvas> {
+        if self.pictures.is_recording() {
+            self.pictures.canvas()
+        } else if self.damage.canvas().is_some() {
+            self.damage.canvas()
+        } else {
+            self.target().map(|surface| surface.canvas())
+        }
+    }
+
+    // the surface a finished frame ends up on before it's presented
+    fn target(&mut self) -> Option<&mut Surface> {
+        if self.fit.is_none() {
+            return self.surface()
+        }
+        let context = &mut self.sk_context;
+        self.fit.as_mut().and_then(|fit| fit.surface(context))
+    }
+
+    // The pixel size of what the scene is drawn on, and the scale factor it's laid out for. A
+    // fixed-size canvas has pixels of its own, so its scene isn't scaled.
+    fn canvas_size(&self) -> ((i32, i32), f32) {
+        match &self.fit {
+            Some(fit) => (fit.size(), 1.0),
+            None => {
+                let size = self.inner_size();
+                ((size.width as i32, size.height as i32), self.scale_factor() as f32)
+            }
+        }
     }
 
     pub fn surface<'a>(&'a mut self) -> Option<&'a mut Surface> {
         if self._surface.is_none(){
+            let _span = tracing::info_span!("create_surface", window = self._id).entered();
             let ct = &mut self._ct.borrow_mut();
             let win = ct.get_current(self._id).unwrap();
             let pixel_format = win.get_pixel_format();
+            let (format, color_type) = self.format;
             let size = win.window().inner_size();
             let backend_render_target = BackendRenderTarget::new_gl(
                 (
@@ -138,9 +807,10 @@ impl GLWindow {
                 {
                     let mut fboid: GLint = 0;
                     unsafe { gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut fboid) };
+                    self.fboid = fboid as GLuint;
                     FramebufferInfo {
                         fboid: fboid.try_into().unwrap(),
-                        format: skia_safe::gpu::gl::Format::RGBA8.into(),
+                        format: format.into(),
                     }
                 },
             );
@@ -148,7 +818,7 @@ impl GLWindow {
                 &mut self.sk_context,
                 &backend_render_target,
                 SurfaceOrigin::BottomLeft,
-                ColorType::RGBA8888,
+                color_type,
                 None,
                 None,
             )
@@ -161,6 +831,17 @@ impl GLWindow {
 impl Drop for GLWindow {
     fn drop(&mut self) {
         let ct = &mut self._ct.borrow_mut();
+        if !ct.contains(self._id) {
+            return // ← its context was lost and couldn't be rebuilt
+        }
+        if ct.get_current(self._id).is_ok() {
+            unsafe {
+                self.readback.destroy();
+                self.gpu_timer.destroy();
+            }
+            // release the cache while this window's context is still around to delete things in
+            self.sk_context.free_gpu_resources();
+        }
         {
             ct.remove(self._id);
         }
@@ -169,45 +850,264 @@ impl Drop for GLWindow {
 }
 
 
+// The demo's content: a box in the window's color with a white bar sweeping across it, plus the
+// text panel if there is one. It's laid out in pixels for a canvas `size` across, with the text
+// scaled up by `sf`, and only depends on `color` so `export_frame` can draw the same frame again.
+fn draw_scene(canvas:&mut Canvas, color:&HSV, (w, h):(f32, f32), sf:f32, text:Option<&mut TextBlock>){
+    canvas.clear(Color::WHITE);
+
+    let fill:Color4f = color.to_color(255).into();
+    canvas.draw_rect(scene_box((w, h)), &Paint::new(fill, None));
+
+    let white:Color4f = Color::WHITE.into();
+    canvas.draw_rect(scene_bar(color, (w, h)), &Paint::new(white, None));
+
+    if let Some(text) = text {
+        canvas.save();
+        canvas.scale((sf, sf));
+        text::draw_demo(canvas, text, (w / sf, h / sf));
+        canvas.restore();
+    }
+}
+
+fn scene_box((w, h):(f32, f32)) -> Rect {
+    let canvas_size = Size::new(w, h);
+    let rect_size = canvas_size * 0.9;
+    Rect::from_point_and_size(
+        Point::new(
+            (canvas_size.width - rect_size.width) / 2.0,
+            (canvas_size.height - rect_size.height) / 2.0,
+        ),
+        rect_size,
+    )
+}
+
+// the bar's position and width both follow the hue, which doubles as the animation's clock
+fn scene_bar(color:&HSV, (w, h):(f32, f32)) -> Rect {
+    let s = 200.0 - 100.0 * ((color.h/180.0 * std::f32::consts::PI).cos() / 2.0 + 0.5);
+    let x = ((color.h/180.0 * std::f32::consts::PI).sin() / 2.0 + 0.5) * w;
+    Rect::new(x-s/2.0, 0.0f32, x+s, h)
+}
+
+// What changes when the scene goes from `prev` to `color`: where the bar was and where it is now,
+// plus the box if its color is any different once it's been rounded to 8 bits
+fn scene_damage(prev:&HSV, color:&HSV, size:(f32, f32)) -> Damage {
+    let mut damage = Damage::default();
+    damage.add(scene_bar(prev, size));
+    damage.add(scene_bar(color, size));
+    if prev.to_color(255) != color.to_color(255) {
+        damage.add(scene_box(size));
+    }
+    damage
+}
+
+// EGL counts damage rectangles up from the bottom of the window
+fn swap_damage(damage:&Damage, (width, height):(i32, i32)) -> Vec<glutin::Rect> {
+    let bounds = Rect::from_iwh(width, height);
+    damage.rects().iter().filter_map(|rect| {
+        let mut rect = *rect;
+        if !rect.intersect(bounds) {
+            return None
+        }
+        Some(glutin::Rect{
+            x: rect.left as u32,
+            y: (bounds.bottom - rect.bottom) as u32,
+            width: rect.width() as u32,
+            height: rect.height() as u32,
+        })
+    }).collect()
+}
+
+fn open_window(el:&EventLoopWindowTarget<()>, ct:&mut Rc<RefCell<ContextTracker>>, index:usize, present_mode:PresentMode, debug:Option<DebugMode>, gpu_options:&GpuOptions) -> GLWindow {
+    // Only the first window waits for vblank. With vsync enabled on every context, each swap can
+    // block for a full refresh and N windows end up running at 1/N of the display's frame rate
+    let present_mode = match index {
+        0 => present_mode,
+        _ => PresentMode::Immediate,
+    };
+
+    let mut window = GLWindow::new(el, ct, present_mode, debug, gpu_options).unwrap();
+    window.color = match index % 4 {
+        0 => HSV::from((0.0, 1.0, 0.2)),
+        1 => HSV::from((90.0, 1.0, 0.5)),
+        2 => HSV::from((180.0, 1.0, 0.75)),
+        _ => HSV::from((270.0, 1.0, 1.0)),
+    };
+    window
+}
+
+// Only one window waits for vblank at a time (see open_window), so when it goes away the oldest
+// window that's left takes over
+fn elect_vsync(windows:&mut HashMap<WindowId, GLWindow>, present_mode:PresentMode){
+    if present_mode.effective() != PresentMode::Fifo || windows.values().any(|w| w.present_mode() == PresentMode::Fifo) {
+        return
+    }
+    if let Some(window) = windows.values_mut().min_by_key(|w| w._id) {
+        match window.set_present_mode(PresentMode::Fifo) {
+            Ok(()) => println!("Context {} now waits for vblank", window._id),
+            Err(msg) => eprintln!("Could not turn on vsync for context {}: {}", window._id, msg),
+        }
+    }
+}
+
+fn report_picture(saved:Result<(std::path::PathBuf, std::path::PathBuf), String>){
+    match saved {
+        Ok((skp, png)) => println!("Saved {} and {}", skp.display(), png.display()),
+        Err(msg) => eprintln!("{}", msg),
+    }
+}
+
+// the steps each cycle of MEMORY_TEST goes through
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MemoryPhase {
+    Baseline, // ← the first window warming up its cache on its own
+    Open,     // ← a batch of windows open alongside it
+    Close,    // ← closing them one at a time
+    Shrink,   // ← the first window made smaller
+    Restore,  // ← and then put back
+    Settle,   // ← waiting for the idle cleanup
+}
+
+fn memory_report(windows:&HashMap<WindowId, GLWindow>) -> MemoryReport {
+    MemoryReport::new(windows.values().map(|w| (w._id, w.memory_usage())).collect())
+}
+
 fn main() {
+    // the drivers read the cache settings from the environment, which can only be changed safely
+    // before any threads have been started (the tracing subscriber starts one)
+    let shader_cache = shader_cache::init("gl");
+
+    env_logger::Builder::from_default_env()
+        .filter_level(log::LevelFilter::Info)
+        .init();
+    let mut trace = trace::init();
+
+    let present_mode = env::parse("PRESENT_MODE").unwrap_or(PresentMode::Fifo);
+
+    let record_format = env::parse("RECORD_FORMAT").unwrap_or(RecordingFormat::Sequence);
+
+    // FONTS can list font files (separated like $PATH) to make available to every window
+    if let Some(paths) = std::env::var_os("FONTS") {
+        let paths:Vec<_> = std::env::split_paths(&paths).collect();
+        match text::use_fonts(&paths, None) {
+            Ok(families) => println!("Loaded fonts: {}", families.join(", ")),
+            Err(msg) => eprintln!("Could not load fonts: {}", msg),
+        }
+    }
+
+    // EXPORT draws the first window's opening frame straight to an SVG or PDF file (picked by its
+    // extension) and exits without creating any windows or GPU contexts
+    if let Some(path) = std::env::var_os("EXPORT").map(std::path::PathBuf::from) {
+        let format = match path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().parse::<ExportFormat>() {
+            Ok(format) => format,
+            Err(msg) => {
+                eprintln!("EXPORT={:?}: {}", path, msg);
+                std::process::exit(1);
+            }
+        };
+        let mut text = std::env::var("TEXT").ok().map(|_| text::demo_text());
+        let data = export::export(format, (400.0, 300.0), |canvas| {
+            draw_scene(canvas, &HSV::from((0.0, 1.0, 0.2)), (400.0, 300.0), 1.0, text.as_mut());
+        });
+        match std::fs::write(&path, data.as_bytes()) {
+            Ok(_) => println!("Exported {}", path.display()),
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        }
+        return
+    }
+
+    let export_format = env::parse("EXPORT_FORMAT").unwrap_or(ExportFormat::Svg);
+
     let el = EventLoop::new();
     let mut ct = Rc::new(RefCell::new(ContextTracker::default()));
 
-    let mut windows = std::collections::HashMap::new();
-    for index in 0..4 {
-        let mut window = GLWindow::new(&el, &mut ct);
-        window.color = match index {
-            0 => HSV::from((0.0, 1.0, 0.2)),
-            1 => HSV::from((90.0, 1.0, 0.5)),
-            2 => HSV::from((180.0, 1.0, 0.75)),
-            _ => HSV::from((270.0, 1.0, 1.0)),
-        };
+    // GL_DEBUG creates debug contexts and logs what the driver reports; GL_DEBUG=strict also
+    // panics after any frame that raised a GL error
+    let debug = env::parse::<DebugMode>("GL_DEBUG");
+
+    // GPU_OPTIONS adjusts how Skia sets up each window's context (see gpu_options.rs)
+    let gpu_options = env::parse::<GpuOptions>("GPU_OPTIONS").unwrap_or_default();
+    if gpu_options != GpuOptions::default() {
+        println!("GPU options: {}", gpu_options);
+    }
+
+    // GPU_BUDGET_MB caps the resource caches of all the windows combined
+    if let Some(megabytes) = env::parse::<usize>("GPU_BUDGET_MB") {
+        memory::set_budget(megabytes * 1024 * 1024);
+    }
 
+    // In benchmark mode windows are added one at a time (up to BENCH_MAX_WINDOWS) and each
+    // configuration runs unthrottled for BENCH_STEP before its per-window frame rate is reported
+    const BENCH_MAX_WINDOWS: usize = 16;
+    const BENCH_STEP: Duration = Duration::from_secs(3);
+    let benchmark = std::env::var("BENCHMARK").is_ok();
+    let mut bench_start = Instant::now();
+    let mut bench_frames:HashMap<WindowId, usize> = HashMap::new();
+
+    // The memory test repeatedly opens a batch of windows alongside the first one and then closes
+    // them one at a time. After each close, every window still open has to have taken on a bigger
+    // share of the budget without its cache growing. The first window is then shrunk and restored,
+    // which leaves the surfaces it drew at the smaller size unused, and the idle cleanup (rather
+    // than an explicit purge) has to release them so its cache drops back to where it started.
+    // Each phase runs for MEMORY_TEST_STEP so the windows have drawn a few frames.
+    const MEMORY_TEST_CYCLES: usize = 5;
+    const MEMORY_TEST_WINDOWS: usize = 8;
+    const MEMORY_TEST_STEP: Duration = Duration::from_secs(1);
+    let memory_test = std::env::var("MEMORY_TEST").is_ok();
+    let mut memory_phase = MemoryPhase::Baseline;
+    let mut memory_baseline = 0;
+    let mut memory_before:HashMap<usize, MemoryUsage> = HashMap::new();
+    let mut memory_cycle = 0;
+    let mut memory_step_start = Instant::now();
+
+    let mut windows = HashMap::new();
+    for index in 0..(if benchmark || memory_test { 1 } else { 4 }) {
+        let window = open_window(&el, &mut ct, index, present_mode, debug, &gpu_options);
         let window_id = window.window_id();
         let ctx_id = window._id;
+        println!("Created {:?} {} ({:?})", window_id, ctx_id, window.present_mode());
         windows.insert(window_id, window);
-        println!("Created {:?} {}", window_id, ctx_id);
     }
 
 
-    let frame_time = Duration::from_micros(1_000_000 / 60);
+    let frame_time = match benchmark {
+        true => Duration::ZERO,
+        false => Duration::from_micros(1_000_000 / 60),
+    };
     let mut next_frame = Instant::now() + frame_time;
 
-    el.run(move |event, _, control_flow| {
+    el.run(move |event, el, control_flow| {
         *control_flow = ControlFlow::Poll;
 
         #[allow(deprecated)]
         match event {
-            Event::LoopDestroyed => {}
+            Event::LoopDestroyed => {
+                windows.clear(); // ← finishes any recordings that are in progress
+                trace.take(); // ← writes out the trace file
+                if let Some(cache) = &shader_cache {
+                    println!("Shader cache: {}", cache.report());
+                }
+            }
             Event::WindowEvent { event, window_id, .. } => match event {
                 WindowEvent::Resized(physical_size) => {
                     if let Some(window) = windows.get_mut(&window_id){
                         window.resize(physical_size);
                     }
                 }
+                WindowEvent::CursorMoved { position, .. } => {
+                    if let Some(window) = windows.get_mut(&window_id){
+                        window.move_pointer(Some(position));
+                    }
+                }
+                WindowEvent::CursorLeft { .. } => {
+                    if let Some(window) = windows.get_mut(&window_id){
+                        window.move_pointer(None);
+                    }
+                }
                 WindowEvent::CloseRequested => {
                     if let Some(_) = windows.remove(&window_id) {
                         println!("Window with ID {:?} has been closed", window_id);
+                        elect_vsync(&mut windows, present_mode);
                     }
                     if windows.is_empty() {
                         *control_flow = ControlFlow::Exit;
@@ -217,12 +1117,64 @@ fn main() {
                 WindowEvent::KeyboardInput {
                     input:
                         KeyboardInput {
+                            state,
                             virtual_keycode,
                             modifiers,
                             ..
                         },
                     ..
                 } => {
+                    let mut overlay = None;
+                    if let (ElementState::Pressed, Some(key), Some(window)) = (state, virtual_keycode, windows.get_mut(&window_id)) {
+                        match key {
+                            VirtualKeyCode::H => window.toggle_hud(),
+                            VirtualKeyCode::T => window.toggle_text(),
+                            VirtualKeyCode::R => match window.is_recording() {
+                                true => window.stop_recording(),
+                                false => {
+                                    let prefix = format!("recording-{}", capture::timestamp());
+                                    window.start_recording(record_format, &prefix);
+                                    println!("Recording to {}…", prefix);
+                                }
+                            }
+                            VirtualKeyCode::S => {
+                                let format = EncodedImageFormat::PNG;
+                                let saved = window.screenshot(format, 100)
+                                    .and_then(|data| capture::save_timestamped(&data, "screenshot", capture::extension(format)));
+                                match saved {
+                                    Ok(path) => println!("Saved {}", path.display()),
+                                    Err(msg) => eprintln!("Screenshot failed: {}", msg),
+                                }
+                            }
+                            VirtualKeyCode::E => {
+                                let data = window.export_frame(export_format);
+                                match capture::save_timestamped(&data, "frame", export_format.extension()) {
+                                    Ok(path) => println!("Exported {}", path.display()),
+                                    Err(msg) => eprintln!("Export failed: {}", msg),
+                                }
+                            }
+                            VirtualKeyCode::F => window.cycle_fit(),
+                            VirtualKeyCode::L => window.abandon_context(),
+                            VirtualKeyCode::D => window.save_picture(),
+                            VirtualKeyCode::P => match window.has_overlay() {
+                                true => overlay = Some(None),
+                                false => match window.snapshot().and_then(|image| window.share_image(&image)) {
+                                    Ok(shared) => overlay = Some(Some(Rc::new(shared))),
+                                    Err(msg) => eprintln!("Could not share snapshot: {}", msg),
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    // the snapshot is uploaded once and then drawn by every window's context
+                    if let Some(shared) = overlay {
+                        for (_, window) in windows.iter_mut() {
+                            window.set_overlay(shared.clone());
+                        }
+                    }
+                    if let (ElementState::Pressed, Some(VirtualKeyCode::M)) = (state, virtual_keycode) {
+                        println!("GPU memory:\n{}", memory_report(&windows));
+                    }
                     if modifiers.logo() {
                         if let Some(VirtualKeyCode::Q) = virtual_keycode {
                             *control_flow = ControlFlow::Exit;
@@ -241,19 +1193,164 @@ fn main() {
 
                 let now = Instant::now();
                 if now >= next_frame{
-                    while next_frame <= now {
+                    let mut missed = 0;
+                    while next_frame <= now && !frame_time.is_zero() {
                         next_frame += frame_time;
+                        missed += 1;
+                    }
+                    for (_, window) in windows.iter_mut() {
+                        window.stats.dropped += missed.max(1) - 1;
                     }
 
+                    // render every window before presenting any of them, then swap the
+                    // windows that don't wait for vblank ahead of the one that does
+                    let _span = tracing::info_span!("tick", windows = windows.len(), missed).entered();
                     for (_, window) in windows.iter_mut() {
-                        window.request_redraw();
+                        window.update();
+                    }
+                    for (_, window) in windows.iter_mut().filter(|(_, w)| w.present_mode() != PresentMode::Fifo) {
+                        window.present();
+                    }
+                    for (_, window) in windows.iter_mut().filter(|(_, w)| w.present_mode() == PresentMode::Fifo) {
+                        window.present();
+                    }
+
+                    // a lost context takes the window's GPU resources with it, so rebuild it and
+                    // re-upload whatever it was showing
+                    let lost:Vec<_> = windows.iter_mut()
+                        .filter_map(|(id, w)| w.context_loss().map(|loss| (*id, loss)))
+                        .collect();
+                    for (window_id, loss) in lost {
+                        let mut window = windows.remove(&window_id).unwrap();
+                        let overlay = window.overlay();
+                        match window.restore(el, loss) {
+                            Ok(()) => {
+                                println!("Restored context {} after it was {:?}", window._id, loss);
+                                // a reset takes the shared textures down along with the contexts
+                                if loss == ContextLoss::Abandoned {
+                                    window.set_overlay(overlay);
+                                }
+                                windows.insert(window.window_id(), window);
+                            }
+                            Err(msg) => {
+                                eprintln!("Could not restore context for {:?}: {}", window_id, msg);
+                                elect_vsync(&mut windows, present_mode);
+                            }
+                        }
                     }
                 }
 
+                if benchmark && now - bench_start >= BENCH_STEP {
+                    let elapsed = (now - bench_start).as_secs_f64();
+                    let rates:Vec<f64> = windows.iter().map(|(id, w)|
+                        (w.stats().frames - bench_frames.get(id).unwrap_or(&0)) as f64 / elapsed
+                    ).collect();
+                    let min = rates.iter().cloned().fold(f64::INFINITY, f64::min);
+                    let max = rates.iter().cloned().fold(0.0, f64::max);
+                    let avg = rates.iter().sum::<f64>() / rates.len().max(1) as f64;
+                    println!(
+                        "{:>2} windows: {:6.1} fps per window (min {:.1}, max {:.1})",
+                        windows.len(), avg, min, max
+                    );
 
+                    if windows.len() >= BENCH_MAX_WINDOWS {
+                        *control_flow = ControlFlow::Exit;
+                    } else {
+                        let window = open_window(el, &mut ct, windows.len(), present_mode, debug, &gpu_options);
+                        windows.insert(window.window_id(), window);
+                    }
+                    bench_frames = windows.iter().map(|(id, w)| (*id, w.stats().frames)).collect();
+                    bench_start = Instant::now();
+                }
+
+                let memory_step = match memory_phase {
+                    MemoryPhase::Settle => memory::IDLE_PURGE + MEMORY_TEST_STEP,
+                    _ => MEMORY_TEST_STEP
+                };
+                if memory_test && now - memory_step_start >= memory_step {
+                    let mb = |bytes:usize| bytes as f64 / (1024.0 * 1024.0);
+                    let first = windows.values().map(|w| w._id).min();
+                    let resize_first = |windows:&mut HashMap<WindowId, GLWindow>, size:LogicalSize<u32>| {
+                        for window in windows.values_mut().filter(|w| Some(w._id) == first) {
+                            window.with_gl_win(|win| win.window().set_inner_size(size));
+                        }
+                    };
+                    let report = memory_report(&windows);
+
+                    memory_phase = match memory_phase {
+                        // the first window has warmed up its cache, so that's the level to return to
+                        MemoryPhase::Baseline => {
+                            memory_baseline = report.total().bytes;
+                            println!("Baseline: {:.1} MB", mb(memory_baseline));
+                            for index in 1..=MEMORY_TEST_WINDOWS {
+                                let window = open_window(el, &mut ct, index, present_mode, debug, &gpu_options);
+                                windows.insert(window.window_id(), window);
+                            }
+                            MemoryPhase::Open
+                        }
+                        MemoryPhase::Open => {
+                            println!("With {} windows:\n{}", windows.len(), report);
+                            memory_before.clear();
+                            MemoryPhase::Close
+                        }
+                        MemoryPhase::Close => {
+                            // the last close has had time to reach the windows that are left
+                            for (id, after) in &report.contexts {
+                                if let Some(before) = memory_before.get(id) {
+                                    if after.limit != memory::share() || after.limit <= before.limit || after.bytes > before.bytes {
+                                        eprintln!("Context {} didn't take over the closed window's share of the budget\n  before: {}\n  after:  {}", id, before, after);
+                                        std::process::exit(1);
+                                    }
+                                }
+                            }
+
+                            let last = windows.values().map(|w| w._id).max().filter(|id| Some(*id) != first);
+                            memory_before = report.contexts.iter().copied().filter(|(id, _)| Some(*id) != last).collect();
+                            match last {
+                                Some(last) => {
+                                    windows.retain(|_, w| w._id != last);
+                                    elect_vsync(&mut windows, present_mode);
+                                    MemoryPhase::Close
+                                }
+                                None => {
+                                    resize_first(&mut windows, LogicalSize::new(320, 240));
+                                    MemoryPhase::Shrink
+                                }
+                            }
+                        }
+                        MemoryPhase::Shrink => {
+                            resize_first(&mut windows, LogicalSize::new(400, 300));
+                            MemoryPhase::Restore
+                        }
+                        MemoryPhase::Restore => {
+                            memory_before = report.contexts.iter().copied().collect();
+                            MemoryPhase::Settle
+                        }
+                        MemoryPhase::Settle => {
+                            // what the smaller surfaces held is now idle, so the deferred cleanup frees it
+                            memory_cycle += 1;
+                            let (before, used) = (memory_before.values().map(|u| u.bytes).sum::<usize>(), report.total().bytes);
+                            println!("Cycle {}: {:.1} MB after closing {} windows, down from {:.1} MB before the idle cleanup", memory_cycle, mb(used), MEMORY_TEST_WINDOWS, mb(before));
+                            if used >= before || used > memory_baseline {
+                                eprintln!("GPU memory did not return to baseline ({:.1} MB):\n{}", mb(memory_baseline), report);
+                                std::process::exit(1);
+                            }
+
+                            if memory_cycle == MEMORY_TEST_CYCLES {
+                                println!("GPU memory returned to baseline after every cycle");
+                                *control_flow = ControlFlow::Exit;
+                            } else {
+                                for index in 1..=MEMORY_TEST_WINDOWS {
+                                    let window = open_window(el, &mut ct, index, present_mode, debug, &gpu_options);
+                                    windows.insert(window.window_id(), window);
+                                }
+                            }
+                            MemoryPhase::Open
+                        }
+                    };
+                    memory_step_start = Instant::now();
+                }
             },
         }
     });
 }
-
-
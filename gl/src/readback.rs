@@ -0,0 +1,154 @@
+use std::collections::VecDeque;
+use gl::types::*;
+use skia_safe::{AlphaType, ColorType, Data, Image, ImageInfo};
+
+pub type ReadbackCallback = Box<dyn FnOnce(Result<Image, String>)>;
+
+struct PendingRead {
+    pbo: GLuint,
+    fence: GLsync,
+    size: (i32, i32),
+    callback: ReadbackCallback,
+}
+
+// Non-blocking framebuffer reads using pixel buffer objects. `glReadPixels` into a bound PBO
+// returns immediately and the copy happens whenever the GPU gets to it; a fence tells us when
+// the buffer can be mapped without stalling, which is usually a frame or two later.
+//
+// All of the methods must be called with the owning window's context current, and since they
+// touch GL state behind Skia's back, the DirectContext needs to be `reset` afterwards.
+#[derive(Default)]
+pub struct PboReadback {
+    pending: VecDeque<PendingRead>,
+    free: Vec<GLuint>,
+    scaled: Option<(GLuint, GLuint, (i32, i32))>, // fbo, renderbuffer & size used for downscaling
+}
+
+impl PboReadback {
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    // Start copying the framebuffer into a PBO, first shrinking it by `scale` (via a linear-filtered
+    // blit into an offscreen renderbuffer) if it's less than 1.0
+    pub unsafe fn request(&mut self, src_fbo:GLuint, (width, height):(i32, i32), scale:f32, callback:ReadbackCallback){
+        let size = match scale < 1.0 {
+            true => (((width as f32 * scale) as i32).max(1), ((height as f32 * scale) as i32).max(1)),
+            false => (width, height),
+        };
+
+        let read_fbo = match size == (width, height) {
+            true => src_fbo,
+            false => {
+                let dst_fbo = self.scaled_target(size);
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, src_fbo);
+                gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, dst_fbo);
+                gl::BlitFramebuffer(
+                    0, 0, width, height, 0, 0, size.0, size.1, gl::COLOR_BUFFER_BIT, gl::LINEAR
+                );
+                dst_fbo
+            }
+        };
+
+        let pbo = self.free.pop().unwrap_or_else(|| {
+            let mut pbo = 0;
+            gl::GenBuffers(1, &mut pbo);
+            pbo
+        });
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pbo);
+        gl::BufferData(gl::PIXEL_PACK_BUFFER, (size.0 * size.1 * 4) as GLsizeiptr, std::ptr::null(), gl::STREAM_READ);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, read_fbo);
+        gl::ReadPixels(0, 0, size.0, size.1, gl::RGBA, gl::UNSIGNED_BYTE, std::ptr::null_mut());
+        let fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+
+        self.pending.push_back(PendingRead{ pbo, fence, size, callback });
+    }
+
+    // Hand off every read whose fence has already signalled, leaving the rest for later
+    pub unsafe fn poll(&mut self){
+        while let Some(read) = self.pending.front() {
+            match gl::ClientWaitSync(read.fence, 0, 0) {
+                gl::ALREADY_SIGNALED | gl::CONDITION_SATISFIED => {},
+                _ => break
+            }
+
+            let PendingRead{ pbo, fence, size, callback } = self.pending.pop_front().unwrap();
+            gl::DeleteSync(fence);
+            let result = read_buffer(pbo, size);
+            self.free.push(pbo);
+            callback(result);
+        }
+    }
+
+    pub unsafe fn destroy(&mut self){
+        for read in self.pending.drain(..) {
+            gl::DeleteSync(read.fence);
+            gl::DeleteBuffers(1, &read.pbo);
+            (read.callback)(Err("Window was closed before the read completed".to_string()));
+        }
+        for pbo in self.free.drain(..) {
+            gl::DeleteBuffers(1, &pbo);
+        }
+        if let Some((fbo, rbo, _)) = self.scaled.take() {
+            gl::DeleteFramebuffers(1, &fbo);
+            gl::DeleteRenderbuffers(1, &rbo);
+        }
+    }
+
+    // Give up on every read (and forget the buffers) after the context they belonged to was lost,
+    // without making any GL calls
+    pub fn abandon(&mut self){
+        for read in self.pending.drain(..) {
+            (read.callback)(Err("Context was lost before the read completed".to_string()));
+        }
+        self.free.clear();
+        self.scaled = None;
+    }
+
+    unsafe fn scaled_target(&mut self, size:(i32, i32)) -> GLuint {
+        match self.scaled {
+            Some((fbo, _, scaled_size)) if scaled_size == size => fbo,
+            _ => {
+                if let Some((fbo, rbo, _)) = self.scaled.take() {
+                    gl::DeleteFramebuffers(1, &fbo);
+                    gl::DeleteRenderbuffers(1, &rbo);
+                }
+                let (mut fbo, mut rbo) = (0, 0);
+                gl::GenRenderbuffers(1, &mut rbo);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, rbo);
+                gl::RenderbufferStorage(gl::RENDERBUFFER, gl::RGBA8, size.0, size.1);
+                gl::GenFramebuffers(1, &mut fbo);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, rbo);
+                self.scaled = Some((fbo, rbo, size));
+                fbo
+            }
+        }
+    }
+}
+
+// Copy a finished PBO into an image, flipping it right-side-up along the way (GL's rows
+// start at the bottom of the framebuffer)
+unsafe fn read_buffer(pbo:GLuint, (width, height):(i32, i32)) -> Result<Image, String> {
+    let row_bytes = width as usize * 4;
+    let len = row_bytes * height as usize;
+
+    gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pbo);
+    let ptr = gl::MapBufferRange(gl::PIXEL_PACK_BUFFER, 0, len as GLsizeiptr, gl::MAP_READ_BIT) as *const u8;
+    if ptr.is_null() {
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        return Err("Could not map pixel buffer".to_string())
+    }
+
+    let mut pixels = Vec::with_capacity(len);
+    for row in std::slice::from_raw_parts(ptr, len).chunks_exact(row_bytes).rev() {
+        pixels.extend_from_slice(row);
+    }
+    gl::UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+    gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+
+    let info = ImageInfo::new((width, height), ColorType::RGBA8888, AlphaType::Premul, None);
+    Image::from_raster_data(&info, Data::new_copy(&pixels), row_bytes)
+        .ok_or_else(|| "Could not create image from pixel buffer".to_string())
+}
@@ -0,0 +1,95 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use gl::types::*;
+use skia_safe::{
+    AlphaType, Canvas, Color, ColorType, Image, ImageInfo, Paint, PaintStyle, Rect,
+    SamplingOptions, FilterMode, MipmapMode, image::CachingHint,
+    gpu::{gl::TextureInfo, BackendTexture, DirectContext, Mipmapped, SurfaceOrigin}
+};
+use crate::contexts::ContextTracker;
+
+// A texture living in the share group that every GLWindow's context belongs to. It's uploaded
+// once and can then be wrapped by any window's DirectContext, so the same pixels can be drawn
+// everywhere without a trip back through the CPU. The texture is never modified after it's
+// created, so the only synchronization needed is making sure the upload has finished before
+// another context samples from it.
+pub struct SharedImage {
+    texture: GLuint,
+    size: (i32, i32),
+    ct: Rc<RefCell<ContextTracker>>,
+}
+
+impl SharedImage {
+    // Must be called with one of the tracker's contexts current. Since this touches GL state
+    // behind Skia's back, that context's DirectContext needs to be `reset` afterwards.
+    pub unsafe fn upload(ct:&Rc<RefCell<ContextTracker>>, image:&Image) -> Result<Self, String> {
+        let info = ImageInfo::new(image.dimensions(), ColorType::RGBA8888, AlphaType::Premul, None);
+        let row_bytes = info.min_row_bytes();
+        let mut pixels = vec![0u8; row_bytes * image.height() as usize];
+        if !image.read_pixels(&info, pixels.as_mut_slice(), row_bytes, (0, 0), CachingHint::Disallow) {
+            return Err("Could not read pixels from image".to_string())
+        }
+
+        let size = (image.width(), image.height());
+        let mut texture = 0;
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        gl::PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+        gl::TexImage2D(
+            gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, size.0, size.1, 0,
+            gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_ptr() as *const _
+        );
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        // other contexts only see the texture's contents once the commands that filled it have
+        // completed, and a Finish is the only portable way to guarantee that across contexts
+        gl::Finish();
+
+        Ok(SharedImage{ texture, size, ct: Rc::clone(ct) })
+    }
+
+    // Wrap the texture for drawing with a particular window's DirectContext (whose GL context must
+    // be current). Skia borrows the texture rather than taking ownership, so the SharedImage has
+    // to outlive the returned Image.
+    pub fn image(&self, context:&mut DirectContext) -> Option<Image> {
+        let info = TextureInfo{ target: gl::TEXTURE_2D, id: self.texture, format: gl::RGBA8 };
+        let backend_texture = unsafe { BackendTexture::new_gl(self.size, Mipmapped::No, info) };
+        Image::from_texture(
+            context, &backend_texture, SurfaceOrigin::TopLeft,
+            ColorType::RGBA8888, AlphaType::Premul, None
+        )
+    }
+}
+
+impl Drop for SharedImage {
+    fn drop(&mut self) {
+        // any context in the share group can delete the texture; if they've all been closed
+        // it went away along with them
+        if self.ct.borrow_mut().any_current().is_some() {
+            unsafe { gl::DeleteTextures(1, &self.texture) };
+        }
+    }
+}
+
+// Draw an image as an inset along the top-right edge of the canvas (in physical pixels), scaled
+// to a quarter of the canvas's width
+pub fn draw_picture_in_picture(canvas:&mut Canvas, image:&Image){
+    let size = canvas.base_layer_size();
+    let inset = 12.0;
+    let width = size.width as f32 / 4.0;
+    let height = width * image.height() as f32 / image.width().max(1) as f32;
+    let frame = Rect::from_xywh(size.width as f32 - width - inset, inset, width, height);
+
+    canvas.draw_image_rect_with_sampling_options(
+        image, None, frame, SamplingOptions::new(FilterMode::Linear, MipmapMode::None), &Paint::default()
+    );
+
+    let mut border = Paint::new(Color::BLACK.into(), None);
+    border.set_style(PaintStyle::Stroke);
+    border.set_stroke_width(2.0);
+    canvas.draw_rect(frame, &border);
+}
+
@@ -0,0 +1,49 @@
+use std::ffi::c_void;
+use glutin::{platform::ContextTraitExt, window::Window, ContextWrapper, PossiblyCurrent};
+
+// glutin only sets the swap interval when a context is created, so changing it afterwards means
+// calling whichever of the platform's functions for it the context has. Each one reports success
+// its own way: EGL and WGL return true, GLX returns 0, and CGL returns kCGLNoError (also 0).
+type EglSwapInterval = unsafe extern "system" fn(display:*const c_void, interval:i32) -> u32;
+type GlxSwapIntervalMesa = unsafe extern "system" fn(interval:u32) -> i32;
+type GlxSwapIntervalSgi = unsafe extern "system" fn(interval:i32) -> i32;
+type WglSwapIntervalExt = unsafe extern "system" fn(interval:i32) -> i32;
+type CglGetCurrentContext = unsafe extern "system" fn() -> *const c_void;
+type CglSetParameter = unsafe extern "system" fn(context:*const c_void, parameter:i32, value:*const i32) -> i32;
+
+const CGL_CP_SWAP_INTERVAL: i32 = 222;
+
+// Set how many vblanks the current context's swaps wait for (0 to not wait at all)
+pub fn set(win:&ContextWrapper<PossiblyCurrent, Window>, interval:i32) -> Result<(), String> {
+    let lookup = |name:&str| Some(win.get_proc_address(name)).filter(|f| !f.is_null());
+
+    let set = unsafe {
+        if let Some(display) = win.get_egl_display() {
+            lookup("eglSwapInterval").map(|f| {
+                let f:EglSwapInterval = std::mem::transmute(f);
+                f(display, interval) != 0
+            })
+        } else if let Some(f) = lookup("glXSwapIntervalMESA") {
+            let f:GlxSwapIntervalMesa = std::mem::transmute(f);
+            Some(f(interval as u32) == 0)
+        } else if let Some(f) = lookup("glXSwapIntervalSGI") {
+            let f:GlxSwapIntervalSgi = std::mem::transmute(f);
+            Some(f(interval) == 0) // ← refuses an interval of 0
+        } else if let Some(f) = lookup("wglSwapIntervalEXT") {
+            let f:WglSwapIntervalExt = std::mem::transmute(f);
+            Some(f(interval) != 0)
+        } else if let (Some(current), Some(f)) = (lookup("CGLGetCurrentContext"), lookup("CGLSetParameter")) {
+            let current:CglGetCurrentContext = std::mem::transmute(current);
+            let f:CglSetParameter = std::mem::transmute(f);
+            Some(f(current(), CGL_CP_SWAP_INTERVAL, &interval) == 0)
+        } else {
+            None
+        }
+    };
+
+    match set {
+        Some(true) => Ok(()),
+        Some(false) => Err(format!("The driver wouldn't set a swap interval of {}", interval)),
+        None => Err("The context has no way to change its swap interval".to_string())
+    }
+}
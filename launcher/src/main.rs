@@ -0,0 +1,218 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[allow(dead_code)] // ← only the marker is needed here, the backends are the ones printing it
+#[path = "../../common/ready.rs"]
+mod ready;
+
+// a candidate that hasn't presented a frame after this long (which includes compiling shaders on a
+// cold start) is considered stuck
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+// how much of a rejected candidate's stderr to quote when explaining why it failed
+const REASON_LINES: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    Metal,
+    Vulkan,
+    Gl,
+    Raster,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "metal" => Ok(Backend::Metal),
+            "vulkan" => Ok(Backend::Vulkan),
+            "gl" | "opengl" => Ok(Backend::Gl),
+            "raster" | "cpu" => Ok(Backend::Raster),
+            _ => Err(format!("Unknown backend: {:?}", s))
+        }
+    }
+}
+
+impl Backend {
+    // the order backends are tried in: Metal is only an option on macOS, and the CPU is always
+    // there as a last resort
+    fn preferred() -> Vec<Self> {
+        match cfg!(target_os = "macos") {
+            true => vec![Backend::Metal, Backend::Vulkan, Backend::Gl, Backend::Raster],
+            false => vec![Backend::Vulkan, Backend::Gl, Backend::Raster],
+        }
+    }
+
+    // the subproject each backend lives in and the name of the binary it builds
+    fn crate_dir(&self) -> &'static str {
+        match self {
+            Backend::Metal => "metal",
+            Backend::Vulkan => "vulkan",
+            Backend::Gl => "gl",
+            Backend::Raster => "raster",
+        }
+    }
+
+    fn binary(&self) -> String {
+        let name = match self {
+            Backend::Metal => "mtl-win",
+            Backend::Vulkan => "rafx-win",
+            Backend::Gl => "gl-win",
+            Backend::Raster => "raster-win",
+        };
+        format!("{}{}", name, std::env::consts::EXE_SUFFIX)
+    }
+
+    // Look next to the launcher first (for installed copies), then in the subproject's own
+    // target directory
+    fn executable(&self) -> Result<PathBuf, String> {
+        let beside = std::env::current_exe().ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join(self.binary())));
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+        let built = ["release", "debug"].iter()
+            .map(|profile| root.join(self.crate_dir()).join("target").join(profile).join(self.binary()));
+
+        beside.into_iter().chain(built)
+            .find(|path| path.is_file())
+            .ok_or_else(|| format!("{} hasn't been built (run `cargo build` in {}/)", self.binary(), self.crate_dir()))
+    }
+}
+
+// Start a backend and wait for it to report that a window presented its first frame (see
+// common/ready.rs). Its output is passed through as usual, but the last few lines of stderr are
+// kept around to explain the failure if it exits or gets stuck before then. A clean exit only
+// counts as success when it wasn't meant to open a window in the first place (`windowless`).
+fn launch(backend:Backend, args:&[String], windowless:bool) -> Result<Child, String> {
+    if backend == Backend::Metal && !cfg!(target_os = "macos") {
+        return Err("Metal is only available on macOS".to_string())
+    }
+
+    let exe = backend.executable()?;
+    let mut child = Command::new(&exe)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("{}: {}", exe.display(), e))?;
+
+    let (presented_tx, presented) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                match line == ready::FIRST_FRAME {
+                    true => { let _ = presented_tx.send(()); }
+                    false => println!("{}", line),
+                }
+            }
+        });
+    }
+
+    let recent = Arc::new(Mutex::new(VecDeque::with_capacity(REASON_LINES)));
+    if let Some(stderr) = child.stderr.take() {
+        let recent = Arc::clone(&recent);
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                eprintln!("{}", line);
+                let mut recent = recent.lock().unwrap();
+                if recent.len() == REASON_LINES {
+                    recent.pop_front();
+                }
+                recent.push_back(line);
+            }
+        });
+    }
+
+    let reason = |what:String| {
+        let recent:Vec<_> = recent.lock().unwrap().iter().cloned().collect();
+        match recent.is_empty() {
+            true => what,
+            false => format!("{}: {}", what, recent.join(" / ")),
+        }
+    };
+
+    let started = Instant::now();
+    loop {
+        if presented.try_recv().is_ok() {
+            return Ok(child)
+        }
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() && windowless => return Ok(child), // ← it had nothing to show
+            Ok(Some(status)) => {
+                thread::sleep(Duration::from_millis(50)); // ← let the last of its output arrive
+                if presented.try_recv().is_ok() {
+                    return Ok(child) // ← it did get a window up, so its exit status is the user's to see
+                }
+                return Err(reason(format!("exited with {} before presenting a frame", status)))
+            }
+            Ok(None) if started.elapsed() >= STARTUP_TIMEOUT => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(reason(format!("didn't present a frame within {} seconds", STARTUP_TIMEOUT.as_secs())))
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(50)),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+// Separate the launcher's own `--backend <name>` (or `--backend=<name>`) from the arguments meant
+// for the backend
+fn split_args(mut args:impl Iterator<Item = String>) -> (Option<String>, Vec<String>) {
+    let mut backend = None;
+    let mut rest = vec![];
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("--backend") {
+            Some("") => backend = args.next(),
+            Some(name) if name.starts_with('=') => backend = Some(name[1..].to_string()),
+            _ => rest.push(arg),
+        }
+    }
+    (backend, rest)
+}
+
+fn run(mut child:Child) -> ExitStatus {
+    child.wait().expect("Lost track of the backend process")
+}
+
+fn main() {
+    env_logger::Builder::from_default_env()
+        .filter_level(log::LevelFilter::Info)
+        .init();
+
+    // SKIA_BACKEND (or --backend <name>) skips the fallback chain and runs just that backend; any
+    // other arguments are passed along to it
+    let (backend, args) = split_args(std::env::args().skip(1));
+    let forced = backend
+        .or_else(|| std::env::var("SKIA_BACKEND").ok())
+        .map(|name| name.parse::<Backend>().unwrap_or_else(|msg| {
+            log::error!("{}", msg);
+            std::process::exit(2);
+        }));
+
+    // EXPORT makes a backend write a file and exit without opening any windows
+    let windowless = std::env::var_os("EXPORT").is_some();
+
+    let candidates = match forced {
+        Some(backend) => vec![backend],
+        None => Backend::preferred(),
+    };
+
+    for backend in candidates {
+        log::info!("Trying the {:?} backend…", backend);
+        match launch(backend, &args, windowless) {
+            Ok(child) => {
+                log::info!("Using the {:?} backend", backend);
+                std::process::exit(run(child).code().unwrap_or(1));
+            }
+            Err(reason) => log::warn!("Rejected the {:?} backend: {}", backend, reason),
+        }
+    }
+
+    log::error!("None of the backends could open a window");
+    std::process::exit(1);
+}
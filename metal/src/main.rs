@@ -1,35 +1,165 @@
 use std::collections::HashMap;
 use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 use cocoa::{appkit::NSView, base::id as cocoa_id};
 use core_graphics_types::geometry::CGSize;
 use foreign_types_shared::{ForeignType, ForeignTypeRef};
-use metal_rs::{Device, MTLPixelFormat, MetalLayer, CommandQueue};
+use metal_rs::{CommandBuffer, CommandQueue, Device, MTLCommandBufferStatus, MTLPixelFormat, MetalDrawableRef, MetalLayer};
 use objc::{rc::autoreleasepool, runtime::YES};
 
 use skia_safe::{
-    scalar, HSV, Color4f, ColorType, Paint, Point, Rect, Size, Surface, Color,
+    Canvas, HSV, Color4f, ColorType, Data, EncodedImageFormat, Image, Paint, Point, Rect, Size, Surface, Color,
     gpu::{mtl, BackendRenderTarget, DirectContext, SurfaceOrigin}
 };
 
+#[path = "../../common/stats.rs"]
+mod stats;
+use stats::{FrameStats, FrameTiming};
+
+#[path = "../../common/text.rs"]
+mod text;
+use text::TextBlock;
+
+#[path = "../../common/capture.rs"]
+mod capture;
+
+mod readback;
+use readback::{DeferredReadback, ReadbackCallback};
+
+#[path = "../../common/recording.rs"]
+mod recording;
+use recording::{Recorder, RecordingFormat};
+
+mod shared;
+use shared::SharedImage;
+
+#[path = "../../common/memory.rs"]
+mod memory;
+use memory::{BudgetShare, MemoryReport, MemoryUsage};
+
+#[path = "../../common/trace.rs"]
+mod trace;
+
+#[path = "../../common/ready.rs"]
+mod ready;
+
+#[path = "../../common/gpu_options.rs"]
+mod gpu_options;
+use gpu_options::GpuOptions;
+
+#[path = "../../common/picture.rs"]
+mod picture;
+use picture::FrameRecorder;
+
+#[path = "../../common/export.rs"]
+mod export;
+use export::ExportFormat;
+
+#[path = "../../common/damage.rs"]
+mod damage;
+use damage::{Damage, DamageMode, DamageTracker};
+
+#[path = "../../common/env.rs"]
+mod env;
+
+#[path = "../../common/shader_cache.rs"]
+mod shader_cache;
+use shader_cache::ProgramCache;
+
+#[path = "../../common/fit.rs"]
+mod fit;
+use fit::CanvasFit;
+
 use winit::{
-    dpi::{LogicalSize, LogicalPosition, PhysicalSize},
-    event::{Event, WindowEvent},
+    dpi::{LogicalSize, LogicalPosition, PhysicalPosition, PhysicalSize},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     platform::macos::WindowExtMacOS,
-    window::{WindowBuilder, Window},
+    window::{WindowBuilder, Window, WindowId},
 };
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PresentMode {
+    Fifo,
+    Mailbox,
+    Immediate,
+    Adaptive,
+}
+
+impl std::str::FromStr for PresentMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fifo" | "vsync" => Ok(PresentMode::Fifo),
+            "mailbox" => Ok(PresentMode::Mailbox),
+            "immediate" => Ok(PresentMode::Immediate),
+            "adaptive" => Ok(PresentMode::Adaptive),
+            _ => Err(format!("Unknown present mode: {:?}", s))
+        }
+    }
+}
+
+// What a window's context has lost, if anything
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContextLoss {
+    Abandoned, // Skia gave up on its context, but the device is still usable
+    Device,    // the GPU stopped executing our work (e.g., it was reset or removed)
+}
+
+// A context for the queue's device, along with the shader cache it was given (which has to
+// outlive it)
+fn new_context(queue:&CommandQueue, options:&GpuOptions) -> Option<(DirectContext, Option<Arc<ProgramCache>>)> {
+    let backend = unsafe {
+        mtl::BackendContext::new(
+            queue.device().as_ptr() as mtl::Handle,
+            queue.as_ptr() as mtl::Handle,
+            std::ptr::null(),
+        )
+    };
+    let mut options = options.context_options();
+    let program_cache = shader_cache::attach(&mut options, queue.device().name());
+    DirectContext::new_metal(&backend, &options).map(|context| (context, program_cache))
+}
+
+// each window owns its own DirectContext; number them so they can be told apart in the HUD
+static NEXT_CONTEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
 struct MetalWindow {
+    id: usize,
     window: Window,
     layer: MetalLayer,
     context: RefCell<DirectContext>,
+    _program_cache: Option<Arc<ProgramCache>>, // ← must outlive the context
     queue: CommandQueue,
+    present_mode: PresentMode,
+    stats: FrameStats,
+    show_hud: bool,
+    text: Option<TextBlock>,
+    readback: DeferredReadback,
+    readback_requests: Vec<(f32, ReadbackCallback)>,
+    recorder: Option<Rc<RefCell<Recorder>>>,
+    // this context's handle on a shared image & the image itself. The handle doesn't own the
+    // texture it wraps, so it comes first in the tuple to be dropped before the SharedImage
+    overlay: Option<(Image, Rc<SharedImage>)>,
+    budget: BudgetShare,
+    occluded: bool,
+    last_commands: Option<CommandBuffer>, // ← checked for errors on the following frame
+    gpu_options: GpuOptions,
+    pictures: FrameRecorder,
+    record_pictures: bool,   // ← record every frame as a picture before drawing it
+    picture_requested: bool, // ← record the next frame and save it
+    damage: DamageTracker, // ← holds the last frame, redrawing only what the scene reports as changed
+    drawn: HSV, // ← the color the last frame was drawn with
+    fit: Option<CanvasFit>, // ← draw to a fixed-size canvas that's then scaled into the window
+    pointer: Option<Point>, // ← where the pointer is on that canvas
     color: HSV
 }
 
 impl MetalWindow {
-    pub fn new(window:Window) -> Self {
+    pub fn new(window:Window, present_mode:PresentMode, gpu_options:&GpuOptions) -> Self {
         let device = Device::system_default().expect("no device found");
 
         let layer = {
@@ -38,6 +168,7 @@ impl MetalWindow {
             layer.set_device(&device);
             layer.set_pixel_format(MTLPixelFormat::BGRA8Unorm);
             layer.set_presents_with_transaction(false);
+            layer.set_framebuffer_only(false); // ← so screenshots can read the drawable back
 
             unsafe {
                 let view = window.ns_view() as cocoa_id;
@@ -49,103 +180,548 @@ impl MetalWindow {
         };
 
         let queue = device.new_command_queue();
+        let (context, _program_cache) = new_context(&queue, gpu_options).unwrap();
+        let context = RefCell::new(context);
+        let id = NEXT_CONTEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let stats = FrameStats::default();
+        let show_hud = std::env::var("HUD").is_ok();
+        let text = std::env::var("TEXT").ok().map(|_| text::demo_text());
+        let mut window = MetalWindow{
+            id, window, layer, context, _program_cache, queue, present_mode, stats, show_hud, text,
+            readback: DeferredReadback::default(), readback_requests: vec![], recorder: None, overlay: None,
+            budget: BudgetShare::new(), occluded: false, last_commands: None, gpu_options: gpu_options.clone(),
+            pictures: FrameRecorder::new(), record_pictures: std::env::var("PICTURES").is_ok(), picture_requested: false,
+            damage: DamageTracker::new(env::parse("DAMAGE").unwrap_or(DamageMode::Full)),
+            drawn: HSV::from((0.5, 1.0, 0.3)), fit: CanvasFit::from_env(), pointer: None,
+            color: HSV::from((0.5, 1.0, 0.3))
+        };
+        window.set_present_mode(present_mode);
+        window
+    }
 
-        let backend = unsafe {
-            mtl::BackendContext::new(
-                device.as_ptr() as mtl::Handle,
-                queue.as_ptr() as mtl::Handle,
-                std::ptr::null(),
-            )
+    pub fn present_mode(&self) -> PresentMode {
+        self.present_mode
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    pub fn toggle_hud(&mut self){
+        self.show_hud = !self.show_hud;
+        self.invalidate();
+    }
+
+    pub fn toggle_text(&mut self){
+        self.text = match self.text {
+            Some(_) => None,
+            None => Some(text::demo_text())
         };
+        self.invalidate();
+    }
 
-        let context = RefCell::new(DirectContext::new_metal(&backend, None).unwrap());
-        MetalWindow{ window, layer, context, queue, color: HSV::from((0.5, 1.0, 0.3)) }
+    // switch to the next way of fitting the canvas into the window (if it has a fixed-size one)
+    pub fn cycle_fit(&mut self){
+        if let Some(fit) = self.fit.as_mut() {
+            println!("Context {} fits its canvas with {:?}", self.id, fit.cycle_mode());
+        }
+        self.move_pointer(None); // ← the same spot in the window is somewhere else on the canvas now
+    }
+
+    // Follow the pointer (given in window pixels) across the fixed-size canvas
+    pub fn move_pointer(&mut self, position:Option<PhysicalPosition<f64>>){
+        let (size, sf) = (self.window.inner_size(), self.window.scale_factor() as f32);
+        let pointer = self.fit.as_ref().zip(position).and_then(|(fit, position)| {
+            let window = (size.width as f32, size.height as f32);
+            fit.to_canvas(Point::new(position.x as f32, position.y as f32), window, sf)
+        });
+        if pointer != self.pointer {
+            self.pointer = pointer;
+            self.invalidate();
+        }
+    }
+
+    // redraw the next frame in full, since something the scene doesn't track has changed
+    fn invalidate(&mut self){
+        self.damage.invalidate();
+    }
+
+    // CAMetalLayer has no present modes, so these are approximations: it has no notion of
+    // tearing-on-late-frames so Adaptive behaves like Fifo, and Mailbox vs. Immediate only differ
+    // in how many drawables can be queued up at once
+    pub fn set_present_mode(&mut self, present_mode:PresentMode){
+        let (sync, drawables) = match present_mode {
+            PresentMode::Fifo | PresentMode::Adaptive => (true, 3),
+            PresentMode::Mailbox => (false, 3),
+            PresentMode::Immediate => (false, 2),
+        };
+        self.layer.set_display_sync_enabled(sync);
+        self.layer.set_maximum_drawable_count(drawables);
+        self.present_mode = present_mode;
     }
 
-    pub fn resize(&self, size: PhysicalSize<u32>){
+    pub fn resize(&mut self, size: PhysicalSize<u32>){
         self.layer.set_drawable_size(CGSize::new(size.width as f64, size.height as f64));
+        self.stats.surface_recreations += 1;
+        if let Some((width, height)) = self.fit.as_mut().and_then(|fit| fit.lock_aspect((size.width, size.height))) {
+            self.window.set_inner_size(PhysicalSize::new(width, height));
+        }
         self.window.request_redraw();
     }
 
+    // Release every cached GPU resource that isn't currently in use
+    pub fn purge(&mut self){
+        self.context.borrow_mut().purge_unlocked_resources(None, false);
+    }
+
+    // Stop drawing while the window can't be seen, and let go of what it had cached in the meantime
+    pub fn set_occluded(&mut self, occluded:bool){
+        self.occluded = occluded;
+        if occluded {
+            self.purge();
+        }
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::of(&self.context.borrow())
+    }
+
+    pub fn context_loss(&self) -> Option<ContextLoss> {
+        let failed = matches!(&self.last_commands, Some(cb) if cb.status() == MTLCommandBufferStatus::Error);
+        if failed {
+            Some(ContextLoss::Device)
+        } else if self.context.borrow_mut().abandoned() {
+            Some(ContextLoss::Abandoned)
+        } else {
+            None
+        }
+    }
+
+    // Simulate losing the context, to exercise `restore`
+    pub fn abandon_context(&mut self){
+        self.context.borrow_mut().abandon();
+    }
+
+    // Rebuild the DirectContext (and, if the device went away, pick up whichever one is now the
+    // system default along with a fresh queue). The window itself stays put, but everything it had
+    // on the GPU is gone, including its handle on any shared image, so it's up to the caller to
+    // upload it again.
+    pub fn restore(&mut self, loss:ContextLoss) -> Result<(), String> {
+        self.context.borrow_mut().abandon();
+        self.overlay = None;
+        self.last_commands = None;
+        self.readback.abandon();
+        self.damage.abandon();
+        if let Some(fit) = self.fit.as_mut() {
+            fit.abandon();
+        }
+
+        if loss == ContextLoss::Device {
+            let device = Device::system_default().ok_or_else(|| "No Metal device found".to_string())?;
+            self.layer.set_device(&device);
+            self.queue = device.new_command_queue();
+        }
+
+        let (context, program_cache) = new_context(&self.queue, &self.gpu_options).ok_or_else(|| "Could not create a new Skia context".to_string())?;
+        self.context = RefCell::new(context);
+        self._program_cache = program_cache;
+        self.budget = BudgetShare::new();
+        self.stats.context_losses += 1;
+        Ok(())
+    }
+
+    // Show the last frame again without advancing the animation, for when the OS asks for a redraw
+    // (after the window is uncovered, say). The scene is only drawn again if there's no frame of
+    // the drawable's current size to show.
     pub fn redraw(&mut self){
+        if self.occluded || self.context_loss().is_some() {
+            return
+        }
+        let size = self.layer.drawable_size();
+        let retained = match self.fit.as_ref() {
+            Some(fit) => fit.has_frame(),
+            None => self.damage.has_frame((size.width as i32, size.height as i32))
+        };
+        match retained {
+            true => self.present_last(),
+            false => { self.render(false); }
+        }
+    }
+
+    // advance the animation and draw & present its next frame
+    pub fn update(&mut self){
+        if self.occluded {
+            return
+        }
         self.color.h += 1.0;
         self.color.h %= 360.0;
+        self.render(false);
+    }
 
-        let s = 200.0 - 100.0 * ((self.color.h/180.0 * std::f32::consts::PI).cos() / 2.0 + 0.5);
-        let mut x = (self.color.h/180.0 * std::f32::consts::PI).sin() / 2.0 + 0.5;
-        let color:Color4f = self.color.to_color(255).into();
+    pub fn snapshot(&mut self) -> Result<Image, String> {
+        self.render(true)
+            .unwrap_or_else(|| Err("No drawable was available to render into".to_string()))
+    }
 
-        if let Some(drawable) = self.layer.next_drawable() {
-            let drawable_size = {
+    pub fn screenshot(&mut self, format:EncodedImageFormat, quality:u32) -> Result<Data, String> {
+        capture::encode(&self.snapshot()?, format, quality)
+    }
+
+    // Upload an image to the GPU once; the result can then be handed to any window's `set_overlay`
+    pub fn share_image(&self, image:&Image) -> Result<SharedImage, String> {
+        SharedImage::upload(self.queue.device(), image)
+    }
+
+    // Show a shared image as a picture-in-picture inset (or remove it by passing None)
+    pub fn set_overlay(&mut self, shared:Option<Rc<SharedImage>>){
+        let mut context = self.context.borrow_mut();
+        self.overlay = shared.and_then(|shared| {
+            let image = shared.image(&mut context)?;
+            Some((image, shared))
+        });
+        drop(context);
+        self.invalidate();
+    }
+
+    pub fn has_overlay(&self) -> bool {
+        self.overlay.is_some()
+    }
+
+    pub fn overlay(&self) -> Option<Rc<SharedImage>> {
+        self.overlay.as_ref().map(|(_, shared)| Rc::clone(shared))
+    }
+
+    // Capture every frame from here on, writing them to files starting with `prefix`
+    pub fn start_recording(&mut self, format:RecordingFormat, prefix:&str){
+        self.recorder = Some(Rc::new(RefCell::new(Recorder::new(format, prefix))));
+    }
+
+    // The file gets written once the frames that are still being read back have arrived
+    pub fn stop_recording(&mut self){
+        self.recorder = None;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // Capture the next frame that gets rendered without stalling the pipeline. The pixels (shrunk
+    // by `scale` if it's less than 1.0) are delivered to the callback a couple of frames later.
+    pub fn read_pixels_async<F>(&mut self, scale:f32, callback:F)
+        where F:FnOnce(Result<Image, String>) + 'static
+    {
+        self.readback_requests.push((scale, Box::new(callback)));
+    }
+
+    // Save the last frame that was recorded as a picture, or record the next one and save that
+    pub fn save_picture(&mut self){
+        match self.pictures.last() {
+            Some(picture) if self.record_pictures => report_picture(picture::save(picture, "frame")),
+            _ => self.picture_requested = true,
+        }
+    }
+
+    // Draw the window's current frame again with Skia's SVG or PDF backend, at its logical size
+    // (or the size of its fixed canvas, if it has one)
+    pub fn export_frame(&mut self, format:ExportFormat) -> Data {
+        let ((width, height), sf) = self.canvas_size();
+        let (width, height) = (width as f32, height as f32);
+        let (color, text) = (self.color, &mut self.text);
+        export::export(format, (width / sf, height / sf), |canvas| {
+            canvas.scale((1.0 / sf, 1.0 / sf)); // ← the scene itself is laid out in pixels
+            draw_scene(canvas, &color, (width, height), sf, text.as_mut());
+        })
+    }
+
+    // The pixel size of what the scene is drawn on, and the scale factor it's laid out for. A
+    // fixed-size canvas has pixels of its own, so its scene isn't scaled.
+    fn canvas_size(&self) -> ((i32, i32), f32) {
+        match &self.fit {
+            Some(fit) => (fit.size(), 1.0),
+            None => {
                 let size = self.layer.drawable_size();
-                Size::new(size.width as scalar, size.height as scalar)
-            };
+                ((size.width as i32, size.height as i32), self.window.scale_factor() as f32)
+            }
+        }
+    }
 
-            let mut surface = unsafe {
-                let texture_info =
-                    mtl::TextureInfo::new(drawable.texture().as_ptr() as mtl::Handle);
-
-                let backend_render_target = BackendRenderTarget::new_metal(
-                    (drawable_size.width as i32, drawable_size.height as i32),
-                    1,
-                    &texture_info,
-                );
-
-                Surface::from_backend_render_target(
-                    &mut self.context.borrow_mut(),
-                    &backend_render_target,
-                    SurfaceOrigin::TopLeft,
-                    ColorType::BGRA8888,
-                    None,
-                    None,
-                )
-                .unwrap()
-            };
+    fn drawable_surface(&self, drawable:&MetalDrawableRef) -> Surface {
+        let size = self.layer.drawable_size();
+        unsafe {
+            let texture_info =
+                mtl::TextureInfo::new(drawable.texture().as_ptr() as mtl::Handle);
+
+            let backend_render_target = BackendRenderTarget::new_metal(
+                (size.width as i32, size.height as i32),
+                1,
+                &texture_info,
+            );
+
+            Surface::from_backend_render_target(
+                &mut self.context.borrow_mut(),
+                &backend_render_target,
+                SurfaceOrigin::TopLeft,
+                ColorType::BGRA8888,
+                None,
+                None,
+            )
+            .unwrap()
+        }
+    }
+
+    // copy the last frame (or the fixed-size canvas holding it) to a new drawable
+    fn present_last(&mut self){
+        let _span = tracing::info_span!("present_last", window = self.id).entered();
+        if let Some(drawable) = self.layer.next_drawable() {
+            let mut surface = self.drawable_surface(drawable);
+            match self.fit.as_mut() {
+                Some(fit) => {
+                    let size = self.layer.drawable_size();
+                    let sf = self.window.scale_factor() as f32;
+                    fit.present(surface.canvas(), (size.width as f32, size.height as f32), sf);
+                }
+                None => self.damage.repeat(surface.canvas())
+            }
+            surface.flush_and_submit();
+            drop(surface);
+
+            let command_buffer = self.queue.new_command_buffer();
+            command_buffer.present_drawable(drawable);
+            command_buffer.commit();
+            self.last_commands = Some(command_buffer.to_owned());
+        }
+    }
+
+    // draw & present a frame, optionally reading it back from the drawable before it's presented
+    fn render(&mut self, capture:bool) -> Option<Result<Image, String>> {
+        if self.context_loss().is_some() {
+            return None // ← wait for the context to be restored
+        }
+        let _span = tracing::info_span!("render", window = self.id, frame = self.stats.frames).entered();
+
+        if let Some(recorder) = &self.recorder {
+            let recorder = Rc::clone(recorder);
+            self.read_pixels_async(1.0, move |frame| {
+                if let Err(msg) = frame.and_then(|frame| recorder.borrow_mut().add_frame(&frame)) {
+                    eprintln!("Recording: {}", msg);
+                }
+            });
+        }
+
+        tracing::info_span!("readback_poll").in_scope(|| self.readback.poll());
+        self.budget.apply(&mut self.context.borrow_mut());
+
+        let started = Instant::now();
+        let prev = self.drawn;
+
+        let drawable = tracing::info_span!("next_drawable").in_scope(|| self.layer.next_drawable());
+        if let Some(drawable) = drawable {
+            let mut surface = self.drawable_surface(drawable);
 
             // draw(surface.canvas(), self.color.to_color(255).into());
+            let (size, sf) = self.canvas_size();
+            let mut damage = scene_damage(&prev, &self.color, (size.0 as f32, size.1 as f32));
             {
-                let canvas = surface.canvas();
-                let canvas_size = Size::from(canvas.base_layer_size());
-
-                canvas.clear(Color4f::new(1.0, 1.0, 1.0, 1.0));
-
-                let rect_size = canvas_size * 0.95;
-                let rect = Rect::from_point_and_size(
-                    Point::new(
-                        (canvas_size.width - rect_size.width) / 2.0,
-                        (canvas_size.height - rect_size.height) / 2.0,
-                    ),
-                    rect_size,
-                );
-                canvas.draw_rect(rect, &Paint::new(color, None));
-
-                let h = canvas.base_layer_size().height as f32;
-                let w = canvas.base_layer_size().width as f32;
-                x *= w;
-                let line = Rect::new(x-s/2.0, 0.0f32, x+s, h);
-
-                let color:Color4f = Color::WHITE.into();
-                canvas.draw_rect(line, &Paint::new(color, None));
+                let _span = tracing::info_span!("draw").entered();
+                if self.record_pictures || self.picture_requested {
+                    self.pictures.begin(size);
+                }
+                if self.show_hud {
+                    let hud = stats::hud_bounds();
+                    damage.add(Rect::new(hud.left * sf, hud.top * sf, hud.right * sf, hud.bottom * sf));
+                }
+                if self.pictures.is_recording() {
+                    self.damage.invalidate(); // ← pictures hold the whole frame, so it's drawn in full
+                }
+                self.damage.begin(&mut self.context.borrow_mut(), size, 1.0, &mut damage);
+
+                let canvas = match self.pictures.canvas() {
+                    Some(canvas) => canvas,
+                    None => match self.damage.canvas() {
+                        Some(canvas) => canvas,
+                        None => match self.fit.as_mut().and_then(|fit| fit.surface(&mut self.context.borrow_mut())) {
+                            Some(target) => target.canvas(),
+                            None => surface.canvas(),
+                        }
+                    }
+                };
+                let size = Size::from(canvas.base_layer_size());
+                draw_scene(canvas, &self.color, (size.width, size.height), sf, self.text.as_mut());
+
+                if let Some((image, _)) = &self.overlay {
+                    shared::draw_picture_in_picture(canvas, image);
+                }
+
+                if let Some(pointer) = self.pointer {
+                    fit::draw_pointer(canvas, pointer);
+                }
+
+                if self.show_hud {
+                    canvas.save();
+                    canvas.scale((sf, sf));
+                    stats::draw_hud(canvas, &self.stats, "Metal", self.id);
+                    canvas.restore();
+                }
             }
 
+            // the frame ends up on the fixed-size canvas if there is one, or else the drawable
+            let buffer_age = if self.fit.is_some() { 1 } else { 0 };
+            let context = &self.context;
+            let target = match self.fit.as_mut().and_then(|fit| fit.surface(&mut context.borrow_mut())) {
+                Some(target) => target,
+                None => &mut surface,
+            };
 
-            surface.flush_and_submit();
+            if let Some(picture) = self.pictures.finish() {
+                match self.damage.canvas() {
+                    Some(canvas) => canvas.draw_picture(&picture, None, None),
+                    None => target.canvas().draw_picture(&picture, None, None),
+                }
+                if std::mem::take(&mut self.picture_requested) {
+                    report_picture(picture::save(&picture, "frame"));
+                }
+            }
+
+            // a drawable is always presented whole, since CAMetalLayer has nothing like
+            // swap_buffers_with_damage, but only the damage was actually redrawn. Nothing says
+            // what a recycled drawable holds, so only a fixed-size canvas skips copying it all.
+            self.damage.finish(target.canvas(), &damage, buffer_age);
+
+            // the canvas is scaled into the drawable (letterbox and all) afresh every frame
+            if let Some(fit) = self.fit.as_mut() {
+                let size = self.layer.drawable_size();
+                let sf = self.window.scale_factor() as f32;
+                fit.present(surface.canvas(), (size.width as f32, size.height as f32), sf);
+            }
+            self.drawn = self.color;
+
+            let captured = capture.then(|| capture::read_canvas(surface.canvas()));
+            for (scale, callback) in self.readback_requests.drain(..) {
+                self.readback.request(&mut surface, &mut self.context.borrow_mut(), &self.queue, scale, callback);
+            }
+
+            let recorded = Instant::now();
+            tracing::info_span!("flush").in_scope(|| surface.flush_and_submit());
             drop(surface);
 
+            let flushed = Instant::now();
+            let present_span = tracing::info_span!("present_drawable").entered();
             let command_buffer = self.queue.new_command_buffer();
             command_buffer.present_drawable(drawable);
             command_buffer.commit();
+            self.last_commands = Some(command_buffer.to_owned());
+            drop(present_span);
+
+            self.stats.add_frame(FrameTiming{
+                record: recorded - started,
+                flush: flushed - recorded,
+                present: flushed.elapsed(),
+            });
+            ready::first_frame_presented();
+            captured
+        } else {
+            None
         }
+    }
+}
+
+// The demo's content: a box in the window's color with a white bar sweeping across it, plus the
+// text panel if there is one. It's laid out in pixels for a canvas `size` across, with the text
+// scaled up by `sf`, and only depends on `color` so `export_frame` can draw the same frame again.
+fn draw_scene(canvas:&mut Canvas, color:&HSV, (w, h):(f32, f32), sf:f32, text:Option<&mut TextBlock>){
+    canvas.clear(Color4f::new(1.0, 1.0, 1.0, 1.0));
+
+    let fill:Color4f = color.to_color(255).into();
+    canvas.draw_rect(scene_box((w, h)), &Paint::new(fill, None));
 
+    let white:Color4f = Color::WHITE.into();
+    canvas.draw_rect(scene_bar(color, (w, h)), &Paint::new(white, None));
+
+    if let Some(text) = text {
+        canvas.save();
+        canvas.scale((sf, sf));
+        text::draw_demo(canvas, text, (w / sf, h / sf));
+        canvas.restore();
     }
 }
 
+fn scene_box((w, h):(f32, f32)) -> Rect {
+    let canvas_size = Size::new(w, h);
+    let rect_size = canvas_size * 0.95;
+    Rect::from_point_and_size(
+        Point::new(
+            (canvas_size.width - rect_size.width) / 2.0,
+            (canvas_size.height - rect_size.height) / 2.0,
+        ),
+        rect_size,
+    )
+}
+
+// the bar's position and width both follow the hue, which doubles as the animation's clock
+fn scene_bar(color:&HSV, (w, h):(f32, f32)) -> Rect {
+    let s = 200.0 - 100.0 * ((color.h/180.0 * std::f32::consts::PI).cos() / 2.0 + 0.5);
+    let x = ((color.h/180.0 * std::f32::consts::PI).sin() / 2.0 + 0.5) * w;
+    Rect::new(x-s/2.0, 0.0f32, x+s, h)
+}
+
+// What changes when the scene goes from `prev` to `color`: where the bar was and where it is now,
+// plus the box if its color is any different once it's been rounded to 8 bits
+fn scene_damage(prev:&HSV, color:&HSV, size:(f32, f32)) -> Damage {
+    let mut damage = Damage::default();
+    damage.add(scene_bar(prev, size));
+    damage.add(scene_bar(color, size));
+    if prev.to_color(255) != color.to_color(255) {
+        damage.add(scene_box(size));
+    }
+    damage
+}
+
+fn report_picture(saved:Result<(std::path::PathBuf, std::path::PathBuf), String>){
+    match saved {
+        Ok((skp, png)) => println!("Saved {} and {}", skp.display(), png.display()),
+        Err(msg) => eprintln!("{}", msg),
+    }
+}
+
+fn memory_report(windows:&HashMap<WindowId, MetalWindow>) -> MemoryReport {
+    MemoryReport::new(windows.values().map(|w| (w.id, w.memory_usage())).collect())
+}
+
 fn main() {
+    // SHADER_CACHE is read before any threads have been started (the tracing subscriber starts one)
+    let shader_cache = shader_cache::init("metal");
+    let mut trace = trace::init();
     let size:LogicalSize<i32> = LogicalSize::new(400, 300);
     let mut loc:LogicalPosition<i32> = LogicalPosition::new(500, 300);
 
     let event_loop = EventLoop::new();
 
+    let present_mode = env::parse("PRESENT_MODE").unwrap_or(PresentMode::Fifo);
+
+    let record_format = env::parse("RECORD_FORMAT").unwrap_or(RecordingFormat::Sequence);
+
+    let export_format = env::parse("EXPORT_FORMAT").unwrap_or(ExportFormat::Svg);
+
+    // FONTS can list font files (separated like $PATH) to make available to every window
+    if let Some(paths) = std::env::var_os("FONTS") {
+        let paths:Vec<_> = std::env::split_paths(&paths).collect();
+        match text::use_fonts(&paths, None) {
+            Ok(families) => println!("Loaded fonts: {}", families.join(", ")),
+            Err(msg) => eprintln!("Could not load fonts: {}", msg),
+        }
+    }
+
+    // GPU_BUDGET_MB caps the resource caches of all the windows combined
+    if let Some(megabytes) = env::parse::<usize>("GPU_BUDGET_MB") {
+        memory::set_budget(megabytes * 1024 * 1024);
+    }
+
+    // GPU_OPTIONS adjusts how Skia sets up each window's context (see gpu_options.rs)
+    let gpu_options = env::parse::<GpuOptions>("GPU_OPTIONS").unwrap_or_default();
+    if gpu_options != GpuOptions::default() {
+        println!("GPU options: {}", gpu_options);
+    }
+
     let mut windows = HashMap::new();
     for win_id in 0..4 {
         let os_window = WindowBuilder::new()
@@ -158,7 +734,7 @@ fn main() {
         loc.x += 30;
         loc.y += 30;
 
-        let mut window = MetalWindow::new(os_window);
+        let mut window = MetalWindow::new(os_window, present_mode, &gpu_options);
         window.color = match win_id {
             0 => HSV::from((0.0, 1.0, 0.2)),
             1 => HSV::from((90.0, 1.0, 0.5)),
@@ -178,11 +754,44 @@ fn main() {
 
             let now = Instant::now();
             if now > next_frame{
+                let mut missed = 0;
                 while next_frame < now {
                     next_frame += frame_time;
+                    missed += 1;
                 }
+                let tick_span = tracing::info_span!("tick", windows = windows.len(), missed).entered();
                 for (_, win) in windows.iter_mut() {
-                    win.redraw();
+                    win.stats.dropped += missed.max(1) - 1;
+                    win.update();
+                }
+                drop(tick_span);
+
+                // a lost context takes the window's GPU resources with it, so rebuild it and
+                // re-upload whatever it was showing
+                let mut lost = vec![];
+                for (window_id, win) in windows.iter_mut() {
+                    if let Some(loss) = win.context_loss() {
+                        let overlay = win.overlay();
+                        match win.restore(loss) {
+                            Ok(()) => {
+                                println!("Restored context {} after losing its {:?}", win.id, loss);
+                                // textures made on a device that's gone away can't be used with the new one
+                                if loss == ContextLoss::Abandoned {
+                                    win.set_overlay(overlay);
+                                }
+                            }
+                            Err(msg) => {
+                                eprintln!("Could not restore context {}: {}", win.id, msg);
+                                lost.push(*window_id);
+                            }
+                        }
+                    }
+                }
+                for window_id in lost {
+                    windows.remove(&window_id);
+                    if windows.is_empty() {
+                        control_flow.set_exit();
+                    }
                 }
             }
 
@@ -200,13 +809,93 @@ fn main() {
                             }
                         }
                         WindowEvent::Resized(size) => {
-                            if let Some(window) = windows.get(&window_id){
+                            if let Some(window) = windows.get_mut(&window_id){
                                 window.resize(size);
                             }
                         }
+                        WindowEvent::CursorMoved { position, .. } => {
+                            if let Some(window) = windows.get_mut(&window_id){
+                                window.move_pointer(Some(position));
+                            }
+                        }
+                        WindowEvent::CursorLeft { .. } => {
+                            if let Some(window) = windows.get_mut(&window_id){
+                                window.move_pointer(None);
+                            }
+                        }
+                        // hidden & minimized windows are occluded
+                        WindowEvent::Occluded(occluded) => {
+                            if let Some(window) = windows.get_mut(&window_id){
+                                window.set_occluded(occluded);
+                            }
+                        }
+                        WindowEvent::KeyboardInput {
+                            input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::M), .. }, ..
+                        } => {
+                            println!("GPU memory:\n{}", memory_report(&windows));
+                        }
+                        WindowEvent::KeyboardInput {
+                            input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. }, ..
+                        } => {
+                            let mut overlay = None;
+                            if let Some(window) = windows.get_mut(&window_id){
+                                match key {
+                                    VirtualKeyCode::H => window.toggle_hud(),
+                                    VirtualKeyCode::T => window.toggle_text(),
+                                    VirtualKeyCode::R => match window.is_recording() {
+                                        true => window.stop_recording(),
+                                        false => {
+                                            let prefix = format!("recording-{}", capture::timestamp());
+                                            window.start_recording(record_format, &prefix);
+                                            println!("Recording to {}…", prefix);
+                                        }
+                                    }
+                                    VirtualKeyCode::S => {
+                                        let format = EncodedImageFormat::PNG;
+                                        let saved = window.screenshot(format, 100)
+                                            .and_then(|data| capture::save_timestamped(&data, "screenshot", capture::extension(format)));
+                                        match saved {
+                                            Ok(path) => println!("Saved {}", path.display()),
+                                            Err(msg) => eprintln!("Screenshot failed: {}", msg),
+                                        }
+                                    }
+                                    VirtualKeyCode::E => {
+                                        let data = window.export_frame(export_format);
+                                        match capture::save_timestamped(&data, "frame", export_format.extension()) {
+                                            Ok(path) => println!("Exported {}", path.display()),
+                                            Err(msg) => eprintln!("Export failed: {}", msg),
+                                        }
+                                    }
+                                    VirtualKeyCode::F => window.cycle_fit(),
+                                    VirtualKeyCode::L => window.abandon_context(),
+                                    VirtualKeyCode::D => window.save_picture(),
+                                    VirtualKeyCode::P => match window.has_overlay() {
+                                        true => overlay = Some(None),
+                                        false => match window.snapshot().and_then(|image| window.share_image(&image)) {
+                                            Ok(shared) => overlay = Some(Some(Rc::new(shared))),
+                                            Err(msg) => eprintln!("Could not share snapshot: {}", msg),
+                                        }
+                                    }
+                                    _ => {}
+                                }
+                            }
+                            // the snapshot is uploaded once and then drawn by every window's context
+                            if let Some(shared) = overlay {
+                                for (_, window) in windows.iter_mut() {
+                                    window.set_overlay(shared.clone());
+                                }
+                            }
+                        }
                         _ => (),
                     }
                 },
+                Event::LoopDestroyed => {
+                    windows.clear(); // ← finishes any recordings that are in progress
+                    trace.take(); // ← writes out the trace file
+                    if let Some(cache) = &shader_cache {
+                        println!("Shader cache: {}", cache.report());
+                    }
+                }
                 Event::RedrawRequested(window_id) => {
                     if let Some(window) = windows.get_mut(&window_id){
                         window.redraw()
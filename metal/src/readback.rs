@@ -0,0 +1,126 @@
+use std::collections::VecDeque;
+use foreign_types_shared::ForeignType;
+use metal_rs::{
+    Buffer, CommandBuffer, CommandQueueRef, MTLBlitOption, MTLCommandBufferStatus, MTLOrigin,
+    MTLPixelFormat, MTLResourceOptions, MTLSize, MTLStorageMode, MTLTextureUsage, Texture,
+    TextureDescriptor
+};
+use skia_safe::{
+    AlphaType, ColorType, Data, FilterMode, Image, ImageInfo, MipmapMode, Paint, Rect,
+    SamplingOptions, Surface, gpu::{mtl, BackendTexture, DirectContext, Mipmapped, SurfaceOrigin}
+};
+
+pub type ReadbackCallback = Box<dyn FnOnce(Result<Image, String>)>;
+
+// Metal wants the rows of a texture copied into a buffer to be aligned, and 256 bytes satisfies
+// every GPU it runs on
+const ROW_ALIGNMENT: usize = 256;
+
+struct PendingRead {
+    _texture: Texture, // ← the copy the blit reads from, kept alive until it's done
+    buffer: Buffer,
+    commands: CommandBuffer,
+    info: ImageInfo,
+    row_bytes: usize,
+    callback: ReadbackCallback,
+}
+
+// Non-blocking framebuffer reads, the Metal counterpart of GL's PboReadback. The frame is drawn
+// (shrunk by `scale` first if it's less than 1.0) into a texture of our own, which a blit then
+// copies into a buffer in shared memory. Both are queued behind the frame on the window's command
+// queue, and the buffer is only read once its command buffer has completed, which is usually a
+// frame or two later, so the CPU never waits on the GPU.
+#[derive(Default)]
+pub struct DeferredReadback {
+    pending: VecDeque<PendingRead>,
+}
+
+impl DeferredReadback {
+    // Has to be called before the frame is flushed, with the context its surface belongs to
+    pub fn request(&mut self, surface:&mut Surface, context:&mut DirectContext, queue:&CommandQueueRef, scale:f32, callback:ReadbackCallback){
+        match copy(surface, context, queue, scale) {
+            Ok((texture, buffer, commands, info, row_bytes)) => self.pending.push_back(PendingRead{
+                _texture: texture, buffer, commands, info, row_bytes, callback
+            }),
+            Err(msg) => callback(Err(msg)),
+        }
+    }
+
+    // Hand off every read whose copy the GPU has finished, leaving the rest for later
+    pub fn poll(&mut self){
+        while let Some(read) = self.pending.front() {
+            let result = match read.commands.status() {
+                MTLCommandBufferStatus::Completed => read_buffer(&read.buffer, &read.info, read.row_bytes),
+                MTLCommandBufferStatus::Error => Err("The GPU could not copy the frame".to_string()),
+                _ => break // ← command buffers complete in order, so the rest aren't done either
+            };
+            let read = self.pending.pop_front().unwrap();
+            (read.callback)(result);
+        }
+    }
+
+    // Fail every read that's still waiting, e.g., because the context the copies were made with
+    // has been lost
+    pub fn abandon(&mut self){
+        for read in self.pending.drain(..) {
+            (read.callback)(Err("Context was lost before the read completed".to_string()));
+        }
+    }
+}
+
+fn copy(surface:&mut Surface, context:&mut DirectContext, queue:&CommandQueueRef, scale:f32) -> Result<(Texture, Buffer, CommandBuffer, ImageInfo, usize), String> {
+    let frame = surface.image_snapshot();
+    let (width, height) = match scale < 1.0 {
+        true => (((frame.width() as f32 * scale) as i32).max(1), ((frame.height() as f32 * scale) as i32).max(1)),
+        false => (frame.width(), frame.height()),
+    };
+
+    // draw the frame into a texture that can be copied from (unlike the drawable's), shrinking it
+    // along the way so only the reduced pixels need to be transferred
+    let descriptor = TextureDescriptor::new();
+    descriptor.set_pixel_format(MTLPixelFormat::RGBA8Unorm);
+    descriptor.set_width(width as u64);
+    descriptor.set_height(height as u64);
+    descriptor.set_storage_mode(MTLStorageMode::Private);
+    descriptor.set_usage(MTLTextureUsage::RenderTarget | MTLTextureUsage::ShaderRead);
+    let texture = queue.device().new_texture(&descriptor);
+
+    let backend_texture = unsafe {
+        let info = mtl::TextureInfo::new(texture.as_ptr() as mtl::Handle);
+        BackendTexture::new_metal((width, height), Mipmapped::No, &info)
+    };
+    let mut target = Surface::from_backend_texture(
+        context, &backend_texture, SurfaceOrigin::TopLeft, None, ColorType::RGBA8888, None, None
+    ).ok_or_else(|| "Could not wrap texture for readback".to_string())?;
+    target.canvas().draw_image_rect_with_sampling_options(
+        &frame, None, Rect::from_iwh(width, height),
+        SamplingOptions::new(FilterMode::Linear, MipmapMode::None),
+        &Paint::default()
+    );
+    drop(target);
+    context.flush_and_submit(); // ← queues the frame & the copy ahead of the blit
+
+    let info = ImageInfo::new((width, height), ColorType::RGBA8888, AlphaType::Premul, None);
+    let row_bytes = (info.min_row_bytes() + ROW_ALIGNMENT - 1) / ROW_ALIGNMENT * ROW_ALIGNMENT;
+    let len = row_bytes * height as usize;
+    let buffer = queue.device().new_buffer(len as u64, MTLResourceOptions::StorageModeShared);
+
+    let commands = queue.new_command_buffer();
+    let blit = commands.new_blit_command_encoder();
+    blit.copy_from_texture_to_buffer(
+        &texture, 0, 0, MTLOrigin{ x: 0, y: 0, z: 0 },
+        MTLSize{ width: width as u64, height: height as u64, depth: 1 },
+        &buffer, 0, row_bytes as u64, len as u64, MTLBlitOption::empty()
+    );
+    blit.end_encoding();
+    commands.commit();
+
+    Ok((texture, buffer, commands.to_owned(), info, row_bytes))
+}
+
+fn read_buffer(buffer:&Buffer, info:&ImageInfo, row_bytes:usize) -> Result<Image, String> {
+    let len = row_bytes * info.height() as usize;
+    let pixels = unsafe { std::slice::from_raw_parts(buffer.contents() as *const u8, len) };
+    Image::from_raster_data(info, Data::new_copy(pixels), row_bytes)
+        .ok_or_else(|| "Could not create image from snapshot".to_string())
+}
@@ -0,0 +1,74 @@
+use foreign_types_shared::ForeignType;
+use metal_rs::{DeviceRef, MTLPixelFormat, MTLRegion, MTLTextureUsage, Texture, TextureDescriptor};
+use skia_safe::{
+    AlphaType, Canvas, Color, ColorType, Image, ImageInfo, Paint, PaintStyle, Rect,
+    SamplingOptions, FilterMode, MipmapMode, image::CachingHint,
+    gpu::{mtl, BackendTexture, DirectContext, Mipmapped, SurfaceOrigin}
+};
+
+// A texture on the device that every MetalWindow's DirectContext was created with (each window
+// gets its own command queue, but they all use the system default device). It's uploaded once
+// and can then be wrapped by any window's context, so the same pixels can be drawn everywhere
+// without a trip back through the CPU. The texture is never modified after it's created and
+// `replace_region` has finished writing by the time it returns, so no further synchronization
+// between the windows' queues is needed.
+pub struct SharedImage {
+    texture: Texture,
+    size: (i32, i32),
+}
+
+impl SharedImage {
+    pub fn upload(device:&DeviceRef, image:&Image) -> Result<Self, String> {
+        let info = ImageInfo::new(image.dimensions(), ColorType::RGBA8888, AlphaType::Premul, None);
+        let row_bytes = info.min_row_bytes();
+        let mut pixels = vec![0u8; row_bytes * image.height() as usize];
+        if !image.read_pixels(&info, pixels.as_mut_slice(), row_bytes, (0, 0), CachingHint::Disallow) {
+            return Err("Could not read pixels from image".to_string())
+        }
+
+        let (width, height) = (image.width() as u64, image.height() as u64);
+        let descriptor = TextureDescriptor::new();
+        descriptor.set_pixel_format(MTLPixelFormat::RGBA8Unorm);
+        descriptor.set_width(width);
+        descriptor.set_height(height);
+        descriptor.set_usage(MTLTextureUsage::ShaderRead);
+
+        let texture = device.new_texture(&descriptor);
+        texture.replace_region(
+            MTLRegion::new_2d(0, 0, width, height), 0, pixels.as_ptr() as *const _, row_bytes as u64
+        );
+        Ok(SharedImage{ texture, size: (image.width(), image.height()) })
+    }
+
+    // Wrap the texture for drawing with a particular window's DirectContext. Skia retains the
+    // texture itself so the Image stays valid even if the SharedImage is dropped first.
+    pub fn image(&self, context:&mut DirectContext) -> Option<Image> {
+        let backend_texture = unsafe {
+            let info = mtl::TextureInfo::new(self.texture.as_ptr() as mtl::Handle);
+            BackendTexture::new_metal(self.size, Mipmapped::No, &info)
+        };
+        Image::from_texture(
+            context, &backend_texture, SurfaceOrigin::TopLeft,
+            ColorType::RGBA8888, AlphaType::Premul, None
+        )
+    }
+}
+
+// Draw an image as an inset along the top-right edge of the canvas (in physical pixels), scaled
+// to a quarter of the canvas's width
+pub fn draw_picture_in_picture(canvas:&mut Canvas, image:&Image){
+    let size = canvas.base_layer_size();
+    let inset = 12.0;
+    let width = size.width as f32 / 4.0;
+    let height = width * image.height() as f32 / image.width().max(1) as f32;
+    let frame = Rect::from_xywh(size.width as f32 - width - inset, inset, width, height);
+
+    canvas.draw_image_rect_with_sampling_options(
+        image, None, frame, SamplingOptions::new(FilterMode::Linear, MipmapMode::None), &Paint::default()
+    );
+
+    let mut border = Paint::new(Color::BLACK.into(), None);
+    border.set_style(PaintStyle::Stroke);
+    border.set_stroke_width(2.0);
+    canvas.draw_rect(frame, &border);
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use softbuffer::GraphicsContext;
+
+use skia_safe::{HSV, Color, Color4f, Paint, Point, Rect, Size, Surface};
+
+#[path = "../../common/stats.rs"]
+mod stats;
+use stats::{FrameStats, FrameTiming};
+
+#[path = "../../common/trace.rs"]
+mod trace;
+
+#[path = "../../common/ready.rs"]
+mod ready;
+
+use winit::{
+    dpi::{LogicalSize, LogicalPosition},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::{WindowBuilder, Window},
+};
+
+// each window has its own raster surface; number them so they can be told apart in the HUD
+static NEXT_CONTEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+// The last resort when no GPU backend is usable: frames are drawn on the CPU with Skia's raster
+// backend and the pixels are handed to the window system with softbuffer
+struct RasterWindow {
+    id: usize,
+    context: GraphicsContext<Window>,
+    surface: Option<Surface>,
+    pixels: Vec<u32>,
+    stats: FrameStats,
+    show_hud: bool,
+    color: HSV
+}
+
+impl RasterWindow {
+    pub fn new(window:Window) -> Result<Self, String> {
+        let context = unsafe { GraphicsContext::new(window) }
+            .map_err(|_| "Could not create a software framebuffer for the window".to_string())?;
+
+        Ok(RasterWindow{
+            id: NEXT_CONTEXT_ID.fetch_add(1, Ordering::Relaxed),
+            context,
+            surface: None,
+            pixels: vec![],
+            stats: FrameStats::default(),
+            show_hud: std::env::var("HUD").is_ok(),
+            color: HSV::from((0.5, 1.0, 0.3))
+        })
+    }
+
+    pub fn window(&self) -> &Window {
+        self.context.window()
+    }
+
+    pub fn toggle_hud(&mut self){
+        self.show_hud = !self.show_hud;
+    }
+
+    pub fn resize(&mut self){
+        if self.surface.take().is_some() {
+            self.stats.surface_recreations += 1;
+        }
+        self.window().request_redraw();
+    }
+
+    fn surface(&mut self) -> Option<&mut Surface> {
+        if self.surface.is_none() {
+            let size = self.window().inner_size();
+            if size.width == 0 || size.height == 0 {
+                return None // ← minimized
+            }
+            let _span = tracing::info_span!("create_surface", window = self.id).entered();
+            self.surface = Surface::new_raster_n32_premul((size.width as i32, size.height as i32));
+        }
+        self.surface.as_mut()
+    }
+
+    pub fn redraw(&mut self){
+        let _span = tracing::info_span!("render", window = self.id, frame = self.stats.frames).entered();
+        let started = Instant::now();
+        self.color.h += 1.0;
+        self.color.h %= 360.0;
+
+        let s = 200.0 - 100.0 * ((self.color.h/180.0 * std::f32::consts::PI).cos() / 2.0 + 0.5);
+        let mut x = (self.color.h/180.0 * std::f32::consts::PI).sin() / 2.0 + 0.5;
+        let color:Color4f = self.color.to_color(255).into();
+        let (stats, id, show_hud) = (self.stats.clone(), self.id, self.show_hud);
+        let sf = self.window().scale_factor() as f32;
+
+        let mut surface = match self.surface() {
+            Some(_) => self.surface.take().unwrap(),
+            None => return
+        };
+
+        let draw_span = tracing::info_span!("draw").entered();
+        let canvas = surface.canvas();
+        canvas.clear(Color::WHITE);
+
+        let canvas_size = Size::from(canvas.base_layer_size());
+        let rect_size = canvas_size * 0.9;
+        let rect = Rect::from_point_and_size(
+            Point::new(
+                (canvas_size.width - rect_size.width) / 2.0,
+                (canvas_size.height - rect_size.height) / 2.0,
+            ),
+            rect_size,
+        );
+        canvas.draw_rect(rect, &Paint::new(color, None));
+
+        let h = canvas_size.height;
+        let w = canvas_size.width;
+        x *= w;
+        let line = Rect::new(x-s/2.0, 0.0f32, x+s, h);
+        canvas.draw_rect(line, &Paint::new(Color4f::from(Color::WHITE), None));
+
+        if show_hud {
+            canvas.save();
+            canvas.scale((sf, sf));
+            stats::draw_hud(canvas, &stats, "Raster", id);
+            canvas.restore();
+        }
+        drop(draw_span);
+        let recorded = Instant::now();
+
+        // N32 is BGRA on every platform softbuffer supports, which reads as 0xAARRGGBB in a
+        // little-endian u32 (softbuffer ignores the top byte)
+        let (width, height) = (surface.width(), surface.height());
+        let _span = tracing::info_span!("set_buffer").entered();
+        if let Some(pixmap) = surface.peek_pixels() {
+            if let Some(bytes) = pixmap.bytes() {
+                self.pixels.clear();
+                self.pixels.extend(bytes.chunks_exact(4).map(|px| u32::from_le_bytes([px[0], px[1], px[2], px[3]])));
+                self.context.set_buffer(&self.pixels, width as u16, height as u16);
+            }
+        }
+        self.surface = Some(surface);
+
+        self.stats.add_frame(FrameTiming{
+            record: recorded - started,
+            flush: Duration::ZERO,
+            present: recorded.elapsed(),
+        });
+        ready::first_frame_presented();
+    }
+}
+
+fn main() {
+    let mut trace = trace::init();
+    let size:LogicalSize<i32> = LogicalSize::new(400, 300);
+    let mut loc:LogicalPosition<i32> = LogicalPosition::new(500, 300);
+
+    let event_loop = EventLoop::new();
+
+    let mut windows = HashMap::new();
+    for win_id in 0..4 {
+        let os_window = WindowBuilder::new()
+            .with_inner_size(size)
+            .with_position(loc)
+            .with_title("Raster Window".to_string())
+            .build(&event_loop)
+            .unwrap();
+        loc.x += 30;
+        loc.y += 30;
+
+        let mut window = match RasterWindow::new(os_window) {
+            Ok(window) => window,
+            Err(msg) => {
+                eprintln!("{}", msg);
+                std::process::exit(1);
+            }
+        };
+        window.color = match win_id {
+            0 => HSV::from((0.0, 1.0, 0.2)),
+            1 => HSV::from((90.0, 1.0, 0.5)),
+            2 => HSV::from((180.0, 1.0, 0.75)),
+            _ => HSV::from((270.0, 1.0, 1.0)),
+        };
+
+        windows.insert(window.window().id(), window);
+    }
+
+    let frame_time = Duration::from_micros(1_000_000 / 60);
+    let mut next_frame = Instant::now() + frame_time;
+
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+
+        let now = Instant::now();
+        if now > next_frame{
+            let mut missed = 0;
+            while next_frame < now {
+                next_frame += frame_time;
+                missed += 1;
+            }
+            let _span = tracing::info_span!("tick", windows = windows.len(), missed).entered();
+            for (_, win) in windows.iter_mut() {
+                win.stats.dropped += missed.max(1) - 1;
+                win.redraw();
+            }
+        }
+
+        match event {
+            Event::WindowEvent { event, window_id } => match event {
+                WindowEvent::CloseRequested => {
+                    windows.remove(&window_id);
+                    if windows.is_empty() {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+                WindowEvent::Resized(_) => {
+                    if let Some(window) = windows.get_mut(&window_id){
+                        window.resize();
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::H), .. }, ..
+                } => {
+                    if let Some(window) = windows.get_mut(&window_id){
+                        window.toggle_hud();
+                    }
+                }
+                _ => (),
+            },
+            Event::LoopDestroyed => {
+                trace.take(); // ← writes out the trace file
+            }
+            Event::RedrawRequested(window_id) => {
+                if let Some(window) = windows.get_mut(&window_id){
+                    window.redraw();
+                }
+            }
+            _ => {}
+        }
+    });
+}
@@ -0,0 +1,6 @@
+#[path = "../common/build_support.rs"]
+mod build_support;
+
+fn main() {
+    build_support::build_persistent_cache();
+}
@@ -0,0 +1,221 @@
+use std::ffi::CStr;
+use std::fmt;
+use ash::vk;
+use ash::version::{EntryV1_0, InstanceV1_0};
+use skulpin::Renderer;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AdapterType {
+    Integrated,
+    Discrete,
+    Virtual,
+    Cpu,
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PowerPreference {
+    LowPower,
+    HighPerformance,
+}
+
+impl std::str::FromStr for PowerPreference {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "low" | "low-power" | "integrated" => Ok(PowerPreference::LowPower),
+            "high" | "high-performance" | "discrete" => Ok(PowerPreference::HighPerformance),
+            _ => Err(format!("Unknown power preference: {:?}", s))
+        }
+    }
+}
+
+// How to pick an adapter: by its position in the list, by (part of) its name, or by the kind of
+// GPU it is
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdapterSelector {
+    Index(usize),
+    Name(String),
+    Power(PowerPreference),
+}
+
+impl std::str::FromStr for AdapterSelector {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(index) = s.parse() {
+            return Ok(AdapterSelector::Index(index))
+        }
+        match s.parse() {
+            Ok(power) => Ok(AdapterSelector::Power(power)),
+            Err(_) if !s.is_empty() => Ok(AdapterSelector::Name(s.to_lowercase())),
+            Err(_) => Err("Adapter selector is empty".to_string())
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Adapter {
+    pub index: usize,
+    pub name: String,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub kind: AdapterType,
+    pub driver_version: u32,
+    pub api_version: u32,
+}
+
+impl Adapter {
+    pub fn vendor(&self) -> &'static str {
+        match self.vendor_id {
+            0x1002 => "AMD",
+            0x106B => "Apple",
+            0x10DE => "NVIDIA",
+            0x13B5 => "ARM",
+            0x5143 => "Qualcomm",
+            0x8086 => "Intel",
+            0x10005 => "Mesa",
+            _ => "unknown vendor"
+        }
+    }
+
+    pub fn driver(&self) -> String {
+        driver_version(self.vendor_id, self.driver_version)
+    }
+}
+
+// drivers are free to pack their version numbers however they like; NVIDIA's is the only common
+// one that doesn't follow Vulkan's own scheme
+fn driver_version(vendor_id:u32, v:u32) -> String {
+    match vendor_id {
+        0x10DE => format!("{}.{}.{}", v >> 22, (v >> 14) & 0xff, (v >> 6) & 0xff),
+        _ => format!("{}.{}.{}", vk::version_major(v), vk::version_minor(v), vk::version_patch(v)),
+    }
+}
+
+impl fmt::Display for Adapter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {} ({}, {:?}, driver {}, Vulkan {}.{})",
+            self.index, self.name, self.vendor(), self.kind, self.driver(),
+            vk::version_major(self.api_version), vk::version_minor(self.api_version))
+    }
+}
+
+// List the physical devices the Vulkan loader can see, using a throwaway instance (rafx creates
+// its own once the windows are built)
+pub fn enumerate() -> Result<Vec<Adapter>, String> {
+    unsafe {
+        let entry = ash::Entry::new().map_err(|e| format!("Could not load Vulkan: {}", e))?;
+        let app_info = vk::ApplicationInfo::builder().api_version(vk::make_version(1, 1, 0));
+        let create_info = vk::InstanceCreateInfo::builder().application_info(&app_info);
+        let instance = entry.create_instance(&create_info, None)
+            .map_err(|e| format!("Could not create Vulkan instance: {}", e))?;
+
+        let adapters = instance.enumerate_physical_devices().map(|devices| {
+            devices.into_iter().enumerate().map(|(index, device)| {
+                let props = instance.get_physical_device_properties(device);
+                Adapter{
+                    index,
+                    name: CStr::from_ptr(props.device_name.as_ptr()).to_string_lossy().into_owned(),
+                    vendor_id: props.vendor_id,
+                    device_id: props.device_id,
+                    kind: match props.device_type {
+                        vk::PhysicalDeviceType::INTEGRATED_GPU => AdapterType::Integrated,
+                        vk::PhysicalDeviceType::DISCRETE_GPU => AdapterType::Discrete,
+                        vk::PhysicalDeviceType::VIRTUAL_GPU => AdapterType::Virtual,
+                        vk::PhysicalDeviceType::CPU => AdapterType::Cpu,
+                        _ => AdapterType::Other,
+                    },
+                    driver_version: props.driver_version,
+                    api_version: props.api_version,
+                }
+            }).collect()
+        });
+        instance.destroy_instance(None);
+        adapters.map_err(|e| format!("Could not enumerate physical devices: {}", e))
+    }
+}
+
+pub fn select<'a>(adapters:&'a [Adapter], selector:&AdapterSelector) -> Option<&'a Adapter> {
+    let rank = |preference:&PowerPreference, kind:AdapterType| {
+        let order = match preference {
+            PowerPreference::LowPower => [AdapterType::Integrated, AdapterType::Discrete, AdapterType::Virtual, AdapterType::Cpu],
+            PowerPreference::HighPerformance => [AdapterType::Discrete, AdapterType::Integrated, AdapterType::Virtual, AdapterType::Cpu],
+        };
+        order.iter().position(|k| *k == kind).unwrap_or(order.len())
+    };
+
+    match selector {
+        AdapterSelector::Index(index) => adapters.get(*index),
+        AdapterSelector::Name(name) => adapters.iter().find(|a| a.name.to_lowercase().contains(name.as_str())),
+        AdapterSelector::Power(preference) => adapters.iter().min_by_key(|a| rank(preference, a.kind)),
+    }
+}
+
+// rafx always picks the device it rates highest (a discrete GPU if there is one) and has no way
+// to be told otherwise, so the only way to steer it is to hide the other devices from it, through
+// the environment. This has to happen before any renderer is built.
+//   - Mesa's device-select layer (installed alongside the Mesa drivers) can hide everything but
+//     one device, proprietary drivers included, but only tells devices apart by their vendor &
+//     device IDs, so two of the same GPU can't be chosen between.
+//   - MoltenVK can only be told to favor the integrated GPU.
+//   - There's nothing like either on Windows.
+pub fn restrict_to(adapters:&[Adapter], adapter:&Adapter, selector:&AdapterSelector) -> Result<(), String> {
+    if *selector == AdapterSelector::Power(PowerPreference::HighPerformance) {
+        return Ok(()) // ← what rafx picks anyway
+    }
+
+    if cfg!(target_os = "macos") {
+        return match selector {
+            AdapterSelector::Power(PowerPreference::LowPower) => {
+                std::env::set_var("MVK_CONFIG_FORCE_LOW_POWER_GPU", "1");
+                Ok(())
+            }
+            _ => Err("MoltenVK can only be asked for its low-power GPU".to_string())
+        }
+    }
+
+    if cfg!(target_os = "linux") {
+        let twins = adapters.iter()
+            .filter(|other| (other.vendor_id, other.device_id) == (adapter.vendor_id, adapter.device_id))
+            .count();
+        if twins > 1 && !matches!(selector, AdapterSelector::Power(_)) {
+            return Err(format!("{} adapters share its vendor & device IDs, and Mesa's device-select layer can't tell them apart", twins))
+        }
+        if !has_layer(DEVICE_SELECT_LAYER)? {
+            return Err(format!("{} isn't installed", DEVICE_SELECT_LAYER))
+        }
+        std::env::set_var("MESA_VK_DEVICE_SELECT", format!("{:04x}:{:04x}", adapter.vendor_id, adapter.device_id));
+        std::env::set_var("MESA_VK_DEVICE_SELECT_FORCE_DEFAULT_DEVICE", "1");
+        return Ok(())
+    }
+
+    Err("Adapter selection isn't supported on this platform".to_string())
+}
+
+const DEVICE_SELECT_LAYER: &str = "VK_LAYER_MESA_device_select";
+
+fn has_layer(name:&str) -> Result<bool, String> {
+    unsafe {
+        let entry = ash::Entry::new().map_err(|e| format!("Could not load Vulkan: {}", e))?;
+        let layers = entry.enumerate_instance_layer_properties()
+            .map_err(|e| format!("Could not enumerate layers: {}", e))?;
+        Ok(layers.iter().any(|layer| CStr::from_ptr(layer.layer_name.as_ptr()).to_bytes() == name.as_bytes()))
+    }
+}
+
+// The vendor & device IDs and the name of the device a renderer actually ended up on
+pub fn used_by(renderer:&Renderer) -> Option<(u32, u32, String)> {
+    let device_context = renderer.api.device_context();
+    let props = &device_context.vk_device_context()?.physical_device_info().properties;
+    let name = unsafe { CStr::from_ptr(props.device_name.as_ptr()) }.to_string_lossy().into_owned();
+    Some((props.vendor_id, props.device_id, name))
+}
+
+// The device a renderer ended up on along with its driver's version, which is what a cache of
+// compiled programs has to be keyed by
+pub fn driver_of(renderer:&Renderer) -> Option<String> {
+    let device_context = renderer.api.device_context();
+    let props = &device_context.vk_device_context()?.physical_device_info().properties;
+    let name = unsafe { CStr::from_ptr(props.device_name.as_ptr()) }.to_string_lossy();
+    Some(format!("{:04x}:{:04x} {} {}", props.vendor_id, props.device_id, name, driver_version(props.vendor_id, props.driver_version)))
+}
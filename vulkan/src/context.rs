@@ -0,0 +1,51 @@
+use ash::vk::{self, Handle};
+use ash::version::{EntryV1_0, InstanceV1_0};
+use skia_safe::gpu::{ContextOptions, DirectContext};
+use skia_safe::gpu::vk::{BackendContext, GetProcOf};
+use skulpin::Renderer;
+use skulpin::rafx::api::RafxExtents2D;
+use skulpin_renderer::VkSkiaSurface;
+
+// skulpin builds each Renderer's DirectContext with Skia's default options and has no way to pass
+// others in. Swap it for one built with `options` that's the same in every other way: on rafx's
+// device and graphics queue, resolving Vulkan's functions the way skulpin's does. skulpin's
+// surface was allocated by the old context, so it goes first and a new one is made to replace it.
+pub fn rebuild(renderer:&mut Renderer, options:&ContextOptions) -> Result<(), String> {
+    let device_context = renderer.api.device_context();
+    let vk_context = device_context.vk_device_context().ok_or("Not a Vulkan renderer")?;
+    let (entry, instance) = (vk_context.entry(), vk_context.instance());
+    let queue_family = vk_context.queue_family_indices().graphics_queue_family_index;
+    let queue = renderer.graphics_queue.vk_queue().ok_or("Not a Vulkan queue")?;
+
+    let get_proc = |of| unsafe {
+        match of {
+            // skulpin reports Vulkan 1.0 (by not resolving this) to steer clear of a bug in the
+            // memory allocator Skia was built with, and a context beside it has to do the same
+            GetProcOf::Instance(_, name) if std::ffi::CStr::from_ptr(name).to_bytes() == b"vkEnumerateInstanceVersion" => None,
+            GetProcOf::Instance(handle, name) => entry.get_instance_proc_addr(vk::Instance::from_raw(handle as _), name),
+            GetProcOf::Device(handle, name) => instance.get_device_proc_addr(vk::Device::from_raw(handle as _), name),
+        }.map_or(std::ptr::null(), |f| f as _)
+    };
+
+    let context = unsafe {
+        let queue = *queue.queue().queue().lock().unwrap();
+        let backend_context = BackendContext::new(
+            instance.handle().as_raw() as _,
+            vk_context.physical_device().as_raw() as _,
+            vk_context.device().handle().as_raw() as _,
+            (queue.as_raw() as _, queue_family as usize),
+            &get_proc,
+        );
+        DirectContext::new_vulkan(&backend_context, options)
+    }.ok_or("Could not create a Vulkan context")?;
+
+    let swapchain = renderer.swapchain_helper.swapchain_def();
+    let extents = RafxExtents2D{ width: swapchain.width.max(1), height: swapchain.height.max(1) };
+
+    renderer.skia_surface = None;
+    renderer.skia_context.context = context;
+    let surface = VkSkiaSurface::new(&renderer.resource_manager, &mut renderer.skia_context, extents)
+        .map_err(|e| format!("Could not create surface: {}", e))?;
+    renderer.skia_surface = Some(surface);
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use ash::vk;
+use ash::version::DeviceV1_0;
+use skulpin::Renderer;
+use skulpin::rafx::api::{
+    RafxCommandBuffer, RafxCommandBufferDef, RafxCommandPool, RafxCommandPoolDef, RafxDeviceContext,
+    RafxFence, RafxFenceStatus, RafxQueue
+};
+
+// how many frames can be in flight before timing starts skipping them
+const MAX_PENDING: usize = 4;
+
+// the command buffers that write one frame's pair of timestamps, and the fence that says both
+// have been written
+struct Slot {
+    begin: RafxCommandBuffer,
+    end: RafxCommandBuffer,
+    fence: RafxFence,
+}
+
+// Measures how long the GPU spends on each frame with timestamp queries, the Vulkan counterpart
+// of GL's GpuTimer. skulpin records and submits the frame's command buffers itself, so the
+// timestamps are written by command buffers of their own, submitted to the same queue right
+// before Skia's work for the frame and right after skulpin's. Results only become available once
+// the GPU has caught up, so they're collected a few frames later.
+//
+// It has to be dropped before the renderer it was made for.
+pub struct GpuTimer {
+    slots: Vec<Slot>,
+    pending: VecDeque<usize>,
+    free: Vec<usize>,
+    active: Option<usize>,
+    pool: RafxCommandPool, // ← dropped after the command buffers allocated from it
+    query_pool: vk::QueryPool,
+    queue: RafxQueue,
+    device_context: RafxDeviceContext,
+    period: f64, // ← nanoseconds per tick
+    mask: u64,   // ← the bits of a timestamp that are valid
+}
+
+impl GpuTimer {
+    pub fn new(renderer:&Renderer) -> Result<Self, String> {
+        let device_context = renderer.api.device_context();
+        let vk_context = device_context.vk_device_context().ok_or("Not a Vulkan device")?;
+        let info = vk_context.physical_device_info();
+        let family = info.queue_family_indices.graphics_queue_family_index;
+        let valid_bits = info.all_queue_families[family as usize].timestamp_valid_bits;
+        if valid_bits == 0 {
+            return Err("The graphics queue doesn't support timestamps".to_string())
+        }
+
+        let queue = renderer.graphics_queue.clone();
+        let pool = queue.create_command_pool(&RafxCommandPoolDef{ transient: false })
+            .map_err(|e| format!("Could not create command pool: {}", e))?;
+        let query_pool = unsafe {
+            let create_info = vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(2 * (MAX_PENDING + 1) as u32);
+            vk_context.device().create_query_pool(&create_info, None)
+                .map_err(|e| format!("Could not create query pool: {}", e))?
+        };
+
+        // from here on, dropping the timer cleans up whatever has been created so far
+        let mut timer = GpuTimer{
+            slots: vec![], pending: VecDeque::new(), free: vec![], active: None, pool, query_pool,
+            queue, device_context: device_context.clone(),
+            period: vk_context.limits().timestamp_period as f64,
+            mask: if valid_bits >= 64 { !0 } else { (1 << valid_bits) - 1 },
+        };
+
+        // every frame that's waiting on the GPU needs its own pair of queries, plus the one that's
+        // being drawn. The command buffers are recorded once and submitted again each time.
+        let device = vk_context.device();
+        for index in 0..MAX_PENDING + 1 {
+            let (first, second) = (2 * index as u32, 2 * index as u32 + 1);
+            let mut record = |write:&dyn Fn(vk::CommandBuffer)| -> Result<RafxCommandBuffer, String> {
+                let commands = timer.pool.create_command_buffer(&RafxCommandBufferDef{ is_secondary: false })
+                    .map_err(|e| format!("Could not create command buffer: {}", e))?;
+                commands.begin().map_err(|e| format!("Could not begin command buffer: {}", e))?;
+                write(commands.vk_command_buffer().ok_or("Not a Vulkan command buffer")?.vk_command_buffer());
+                commands.end().map_err(|e| format!("Could not end command buffer: {}", e))?;
+                Ok(commands)
+            };
+            let begin = record(&|commands| unsafe {
+                device.cmd_reset_query_pool(commands, query_pool, first, 2);
+                device.cmd_write_timestamp(commands, vk::PipelineStageFlags::TOP_OF_PIPE, query_pool, first);
+            })?;
+            let end = record(&|commands| unsafe {
+                device.cmd_write_timestamp(commands, vk::PipelineStageFlags::BOTTOM_OF_PIPE, query_pool, second);
+            })?;
+            let fence = device_context.create_fence().map_err(|e| format!("Could not create fence: {}", e))?;
+            timer.slots.push(Slot{ begin, end, fence });
+            timer.free.push(index);
+        }
+        Ok(timer)
+    }
+
+    // Start timing the GPU work submitted from here until `end`. Frames are skipped while too many
+    // earlier ones are still waiting on the GPU.
+    pub fn begin(&mut self){
+        if self.active.is_some() || self.pending.len() >= MAX_PENDING {
+            return
+        }
+        if let Some(index) = self.free.pop() {
+            match self.queue.submit(&[&self.slots[index].begin], &[], &[], None) {
+                Ok(_) => self.active = Some(index),
+                Err(_) => self.free.push(index),
+            }
+        }
+    }
+
+    pub fn end(&mut self){
+        if let Some(index) = self.active.take() {
+            let slot = &self.slots[index];
+            match self.queue.submit(&[&slot.end], &[], &[], Some(&slot.fence)) {
+                Ok(_) => self.pending.push_back(index),
+                Err(_) => self.free.push(index),
+            }
+        }
+    }
+
+    // Collect the timings of any frames the GPU has finished (oldest first)
+    pub fn poll(&mut self) -> Vec<Duration> {
+        let mut elapsed = vec![];
+        while let Some(&index) = self.pending.front() {
+            match self.slots[index].fence.get_fence_status() {
+                Ok(RafxFenceStatus::Complete) => {
+                    if let Some(nanos) = self.read(index) {
+                        elapsed.push(Duration::from_nanos(nanos));
+                    }
+                }
+                Ok(_) => break, // ← the queries complete in order, so the rest aren't ready either
+                Err(_) => {} // ← the device is gone, and the queries with it
+            }
+            self.free.push(self.pending.pop_front().unwrap());
+        }
+        elapsed
+    }
+
+    fn read(&self, index:usize) -> Option<u64> {
+        let vk_context = self.device_context.vk_device_context()?;
+        let mut ticks = [0u64; 2];
+        unsafe {
+            vk_context.device().get_query_pool_results(
+                self.query_pool, 2 * index as u32, 2, &mut ticks, vk::QueryResultFlags::TYPE_64
+            ).ok()?;
+        }
+        let elapsed = ticks[1].wrapping_sub(ticks[0]) & self.mask;
+        Some((elapsed as f64 * self.period) as u64)
+    }
+}
+
+impl Drop for GpuTimer {
+    fn drop(&mut self){
+        // the queries still in flight have to be written before the pool goes (a lost device
+        // returns right away)
+        let fences:Vec<_> = self.pending.iter().map(|&index| &self.slots[index].fence).collect();
+        self.device_context.wait_for_fences(&fences).ok();
+        if let Some(vk_context) = self.device_context.vk_device_context() {
+            unsafe { vk_context.device().destroy_query_pool(self.query_pool, None) }
+        }
+    }
+}
@@ -2,55 +2,548 @@
 #![allow(unused_imports)]
 
 use log;
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
 use std::{sync::mpsc, thread};
 use skulpin::{CoordinateSystemHelper, CoordinateSystem, Renderer, RendererBuilder};
 use skulpin::rafx::api::RafxExtents2D;
-use skia_safe::{Point, Size, Rect, Color, Color4f, HSV, Paint};
+use skia_safe::{Canvas, Point, Size, Rect, Color, Color4f, Data, EncodedImageFormat, HSV, Image, Paint};
+#[path = "../../common/stats.rs"]
+mod stats;
+use stats::{FrameStats, FrameTiming};
+
+#[path = "../../common/text.rs"]
+mod text;
+use text::TextBlock;
+
+#[path = "../../common/capture.rs"]
+mod capture;
+
+mod readback;
+use readback::{DeferredReadback, ReadbackCallback};
+
+#[path = "../../common/recording.rs"]
+mod recording;
+use recording::{Recorder, RecordingFormat};
+
+mod shared;
+use shared::SharedImage;
+
+#[path = "../../common/memory.rs"]
+mod memory;
+use memory::{BudgetShare, MemoryReport, MemoryUsage};
+
+mod adapter;
+use adapter::AdapterSelector;
+
+mod validation;
+use validation::{Messenger, Validation};
+
+#[path = "../../common/trace.rs"]
+mod trace;
+
+#[path = "../../common/shader_cache.rs"]
+mod shader_cache;
+use shader_cache::ProgramCache;
+
+mod context;
+
+mod gpu_timer;
+use gpu_timer::GpuTimer;
+
+#[path = "../../common/gpu_options.rs"]
+mod gpu_options;
+use gpu_options::GpuOptions;
+
+#[path = "../../common/ready.rs"]
+mod ready;
+
+mod surface;
+
+#[path = "../../common/picture.rs"]
+mod picture;
+use picture::FrameRecorder;
+
+#[path = "../../common/export.rs"]
+mod export;
+use export::ExportFormat;
+
+#[path = "../../common/damage.rs"]
+mod damage;
+use damage::{Damage, DamageMode, DamageTracker};
+
+#[path = "../../common/env.rs"]
+mod env;
+
+#[path = "../../common/fit.rs"]
+mod fit;
+use fit::CanvasFit;
+
 use winit::{
-    dpi::{LogicalSize, LogicalPosition, PhysicalSize},
+    dpi::{LogicalSize, LogicalPosition, PhysicalPosition, PhysicalSize},
     event::{Event, WindowEvent, KeyboardInput, VirtualKeyCode, ElementState},
-    event_loop::{ControlFlow, EventLoop},
+    event_loop::{ControlFlow, EventLoop, EventLoopProxy},
     platform::macos::WindowExtMacOS,
     window::{WindowBuilder, Window},
 };
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PresentMode {
+    Fifo,
+    Mailbox,
+    Immediate,
+    Adaptive,
+}
+
+impl std::str::FromStr for PresentMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fifo" | "vsync" => Ok(PresentMode::Fifo),
+            "mailbox" => Ok(PresentMode::Mailbox),
+            "immediate" => Ok(PresentMode::Immediate),
+            "adaptive" => Ok(PresentMode::Adaptive),
+            _ => Err(format!("Unknown present mode: {:?}", s))
+        }
+    }
+}
+
+impl PresentMode {
+    // skulpin only exposes a vsync flag: with it on, rafx asks for FIFO; with it off, rafx
+    // prefers MAILBOX, then FIFO_RELAXED, then IMMEDIATE (and then FIFO) depending on the surface
+    fn vsync(&self) -> bool {
+        matches!(self, PresentMode::Fifo | PresentMode::Adaptive)
+    }
+
+    // The mode rafx's swapchain ends up with, given the ones the window's surface supports (in
+    // the order rafx 0.0.14's swapchain.rs tries them)
+    fn presented(&self, supported:&[ash::vk::PresentModeKHR]) -> PresentMode {
+        use ash::vk::PresentModeKHR;
+        let priority:&[_] = match self.vsync() {
+            true => &[(PresentModeKHR::FIFO, PresentMode::Fifo)],
+            false => &[
+                (PresentModeKHR::MAILBOX, PresentMode::Mailbox),
+                (PresentModeKHR::FIFO_RELAXED, PresentMode::Adaptive),
+                (PresentModeKHR::IMMEDIATE, PresentMode::Immediate),
+                (PresentModeKHR::FIFO, PresentMode::Fifo),
+            ],
+        };
+        priority.iter()
+            .find(|(mode, _)| supported.contains(mode))
+            .map_or(PresentMode::Fifo, |(_, presented)| *presented) // ← every driver has to support FIFO
+    }
+}
+
+// Ask the driver which mode the window's swapchain is presenting with, assuming the requested one
+// if it can't be found out
+fn presented_mode(window:&Window, renderer:&Renderer, present_mode:PresentMode) -> PresentMode {
+    match surface::present_modes(window, renderer) {
+        Ok(supported) => present_mode.presented(&supported),
+        Err(e) => {
+            log::warn!("Could not query present modes for {:?}: {}", window.id(), e);
+            present_mode
+        }
+    }
+}
+
+// messages the render threads send to the main thread, to be passed along to every window
+#[derive(Clone)]
+enum UserEvent {
+    Overlay(Option<Arc<SharedImage>>),
+    Resize(LogicalSize<u32>),
+    Tick, // ← time for the animation's next frame
+}
+
+// An event on its way to a window's render thread, along with the span it was sent from (so the
+// work it sets off can be traced back across the thread hop) and when it was sent
+struct Dispatch {
+    event: Event<'static, UserEvent>,
+    span: tracing::Span,
+    sent: Instant,
+}
+
+impl Dispatch {
+    fn new(event:Event<'static, UserEvent>) -> Self {
+        Dispatch{ event, span: tracing::Span::current(), sent: Instant::now() }
+    }
+}
+
+// Time the GPU's work with timestamp queries where the graphics queue supports them
+fn gpu_timer(renderer:&Renderer) -> Option<GpuTimer> {
+    GpuTimer::new(renderer)
+        .map_err(|msg| log::warn!("Can't time the GPU: {}", msg))
+        .ok()
+}
+
+// Have the validation layer report what it finds on the renderer's instance and device
+fn messenger(validation:Option<&Validation>, renderer:&Renderer, id:usize) -> Option<Messenger> {
+    validation?.attach(renderer, id)
+        .map_err(|msg| log::error!("Can't report validation messages: {}", msg))
+        .ok()
+}
+
+fn build_renderer(window:&Window, present_mode:PresentMode, gpu_options:&GpuOptions) -> Result<(Renderer, Option<Arc<ProgramCache>>), String> {
+    let window_size = window.inner_size();
+    let window_extents = RafxExtents2D {
+        width: window_size.width,
+        height: window_size.height,
+    };
+
+    let mut renderer = RendererBuilder::new()
+        .coordinate_system(CoordinateSystem::Logical)
+        .vsync_enabled(present_mode.vsync())
+        .build(window, window_extents)
+        .map_err(|e| format!("Could not create renderer: {}", e))?;
+
+    // skulpin's context has Skia's default options, so it's replaced with one that has ours. The
+    // program cache is keyed by the driver, which is only known once rafx has picked a device.
+    let mut options = gpu_options.context_options();
+    let program_cache = adapter::driver_of(&renderer)
+        .and_then(|driver| shader_cache::attach(&mut options, &driver));
+    context::rebuild(&mut renderer, &options)?;
+    Ok((renderer, program_cache))
+}
+
+// each window owns its own Renderer (and DirectContext); number them so they can be told apart in the HUD
+static NEXT_CONTEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
 struct VulkanWindow{
+    id: usize,
     renderer: Arc<Mutex<Renderer>>,
+    program_cache: Option<Arc<ProgramCache>>, // ← has to outlive the renderer's DirectContext
     window: Window,
+    present_mode: PresentMode, // ← the mode that was asked for
+    gpu_options: GpuOptions,
+    presented: PresentMode,    // ← the one the swapchain actually uses
+    stats: Arc<Mutex<FrameStats>>,
+    show_hud: bool,
+    text: Option<TextBlock>,
+    readback: DeferredReadback,
+    gpu_timer: Option<GpuTimer>, // ← made on the renderer's device, so it's let go of before the renderer
+    validation: Option<Validation>,
+    messenger: Option<Messenger>, // ← registered on the renderer's instance, so that goes first too
+    readback_requests: Vec<(f32, ReadbackCallback)>,
+    recorder: Option<Rc<RefCell<Recorder>>>,
+    overlay: Option<(Arc<SharedImage>, Option<Image>)>, // a shared image & this context's upload of it
+    budget: BudgetShare,
+    memory: Arc<Mutex<MemoryUsage>>,
+    purge: bool,
+    abandon: bool,
+    created: Option<Instant>, // ← cleared once the first frame has been presented
+    pictures: FrameRecorder,
+    record_pictures: bool,   // ← record every frame as a picture before drawing it
+    picture_requested: bool, // ← record the next frame and save it
+    damage: DamageTracker, // ← holds the last frame, redrawing only what the scene reports as changed
+    drawn: HSV, // ← the color the last frame was drawn with
+    fit: Option<CanvasFit>, // ← draw to a fixed-size canvas that's then scaled into the window
+    pointer: Option<Point>, // ← where the pointer is on that canvas
     color: HSV
 }
 
 unsafe impl Send for VulkanWindow {}
 
-impl VulkanWindow {
-    pub fn new(window:Window) -> Option<Self> {
-        let window_size = window.inner_size();
-        let window_extents = RafxExtents2D {
-            width: window_size.width,
-            height: window_size.height,
-        };
+impl Drop for VulkanWindow {
+    fn drop(&mut self){
+        self.store_pipeline_cache();
+        self.readback.abandon(); // ← the copies are made on the renderer's device, which goes next
+        self.gpu_timer = None;
+        self.messenger = None;
+    }
+}
 
-        let renderer = RendererBuilder::new()
-            .coordinate_system(CoordinateSystem::Logical)
-            .build(&window, window_extents);
+impl VulkanWindow {
+    pub fn new(window:Window, present_mode:PresentMode, gpu_options:&GpuOptions, validation:Option<&Validation>) -> Option<Self> {
+        let renderer = build_renderer(&window, present_mode, gpu_options);
 
         let color = HSV::from((0.5, 1.0, 0.3));
+        let id = NEXT_CONTEXT_ID.fetch_add(1, Ordering::Relaxed);
 
-        renderer.map(|renderer|
-            Self{ window, renderer:Arc::new(Mutex::new(renderer)), color }
+        renderer.map(|(renderer, program_cache)|
+            Self{
+                presented: presented_mode(&window, &renderer, present_mode),
+                gpu_timer: gpu_timer(&renderer),
+                messenger: messenger(validation, &renderer, id),
+                validation: validation.cloned(),
+                id,
+                window,
+                renderer:Arc::new(Mutex::new(renderer)),
+                program_cache,
+                present_mode,
+                gpu_options: gpu_options.clone(),
+                stats: Arc::new(Mutex::new(FrameStats::default())),
+                show_hud: std::env::var("HUD").is_ok(),
+                text: std::env::var("TEXT").ok().map(|_| text::demo_text()),
+                readback: DeferredReadback::default(),
+                readback_requests: vec![],
+                recorder: None,
+                overlay: None,
+                budget: BudgetShare::new(),
+                memory: Arc::new(Mutex::new(MemoryUsage::default())),
+                purge: false,
+                abandon: false,
+                created: Some(Instant::now()),
+                pictures: FrameRecorder::new(),
+                record_pictures: std::env::var("PICTURES").is_ok(),
+                picture_requested: false,
+                damage: DamageTracker::new(env::parse("DAMAGE").unwrap_or(DamageMode::Full)),
+                drawn: color,
+                fit: CanvasFit::from_env(),
+                pointer: None,
+                color
+            }
         ).ok()
     }
 
-    pub fn resize(&mut self, _size: PhysicalSize<u32>) {
+    pub fn present_mode(&self) -> PresentMode {
+        self.presented
+    }
+
+    // the window itself lives on its render thread, so hand out a shared handle to its stats
+    pub fn stats(&self) -> Arc<Mutex<FrameStats>> {
+        Arc::clone(&self.stats)
+    }
+
+    // like the stats, the cache usage is updated every frame for the main thread to read
+    pub fn memory(&self) -> Arc<Mutex<MemoryUsage>> {
+        Arc::clone(&self.memory)
+    }
+
+    // Release every cached GPU resource that isn't currently in use. The DirectContext is only
+    // reachable while skulpin is drawing, so this happens at the start of the next frame.
+    pub fn purge(&mut self){
+        self.purge = true;
+    }
+
+    // Simulate losing the context (on the next frame), to exercise `restore`
+    pub fn abandon_context(&mut self){
+        self.abandon = true;
+    }
+
+    // skulpin owns the Vulkan device, the swapchain and the DirectContext together, so recovering
+    // from a lost device (or an abandoned context) means building the window a whole new Renderer.
+    // A shared image being shown gets re-uploaded the next time the window is drawn.
+    pub fn restore(&mut self) -> Result<(), String> {
+        self.readback.abandon();
+        if let Some((_, image)) = self.overlay.as_mut() {
+            *image = None;
+        }
+        self.damage.abandon();
+        if let Some(fit) = self.fit.as_mut() {
+            fit.abandon();
+        }
+
+        self.gpu_timer = None;
+        self.messenger = None;
+        self.store_pipeline_cache();
+        let (renderer, program_cache) = build_renderer(&self.window, self.present_mode, &self.gpu_options)?;
+        self.presented = presented_mode(&self.window, &renderer, self.present_mode);
+        self.gpu_timer = gpu_timer(&renderer);
+        self.messenger = messenger(self.validation.as_ref(), &renderer, self.id);
+        *self.renderer.lock().unwrap() = renderer;
+        self.program_cache = program_cache; // ← only once the old renderer's context is gone
+        self.budget = BudgetShare::new();
+        self.created = Some(Instant::now());
+        self.stats.lock().unwrap().context_losses += 1;
+        Ok(())
+    }
+
+    // Skia only hands the contents of its VkPipelineCache over to the program cache when asked to,
+    // so that happens before the window's context is replaced or dropped
+    fn store_pipeline_cache(&self){
+        if self.program_cache.is_some() {
+            if let Ok(mut renderer) = self.renderer.lock() {
+                renderer.skia_context.context.store_vk_pipeline_cache_data();
+            }
+        }
+    }
+
+    pub fn toggle_hud(&mut self){
+        self.show_hud = !self.show_hud;
+        self.invalidate();
+    }
+
+    pub fn toggle_text(&mut self){
+        self.text = match self.text {
+            Some(_) => None,
+            None => Some(text::demo_text())
+        };
+        self.invalidate();
+    }
+
+    // switch to the next way of fitting the canvas into the window (if it has a fixed-size one)
+    pub fn cycle_fit(&mut self){
+        if let Some(fit) = self.fit.as_mut() {
+            log::info!("Context {} fits its canvas with {:?}", self.id, fit.cycle_mode());
+        }
+        self.move_pointer(None); // ← the same spot in the window is somewhere else on the canvas now
+    }
+
+    // Follow the pointer (given in window pixels) across the fixed-size canvas
+    pub fn move_pointer(&mut self, position:Option<PhysicalPosition<f64>>){
+        let (size, sf) = (self.window.inner_size(), self.window.scale_factor() as f32);
+        let pointer = self.fit.as_ref().zip(position).and_then(|(fit, position)| {
+            let window = (size.width as f32, size.height as f32);
+            fit.to_canvas(Point::new(position.x as f32, position.y as f32), window, sf)
+        });
+        if pointer != self.pointer {
+            self.pointer = pointer;
+            self.invalidate();
+        }
+    }
+
+    // redraw the next frame in full, since something the scene doesn't track has changed
+    fn invalidate(&mut self){
+        self.damage.invalidate();
+    }
+
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        // minimized windows are resized to nothing; nothing they've cached is needed until they come back
+        if size.width == 0 || size.height == 0 {
+            self.purge();
+        }
+        if let Some((width, height)) = self.fit.as_mut().and_then(|fit| fit.lock_aspect((size.width, size.height))) {
+            self.window.set_inner_size(PhysicalSize::new(width, height));
+        }
+        self.stats.lock().unwrap().surface_recreations += 1;
         self.redraw().ok();
     }
 
+    // Show the last frame again without advancing the animation, for when the OS asks for a redraw
+    // (after the window is uncovered, say). The scene is only drawn again if there's no frame of
+    // the window's current size to show.
     pub fn redraw(&mut self) -> Result<(), String>{
+        let size = self.window.inner_size();
+        let retained = match self.fit.as_ref() {
+            Some(fit) => fit.has_frame(),
+            None => self.damage.has_frame((size.width as i32, size.height as i32))
+        };
+        match retained {
+            true => self.present_last(),
+            false => self.render(false).map(|_| ())
+        }
+    }
+
+    // advance the animation and draw & present its next frame
+    pub fn update(&mut self) -> Result<(), String>{
         self.color.h += 1.0;
         self.color.h %= 360.0;
+        self.render(false).map(|_| ())
+    }
+
+    // copy the last frame (or the fixed-size canvas holding it) to the window again
+    fn present_last(&mut self) -> Result<(), String>{
+        let _span = tracing::info_span!("present_last", window = self.id).entered();
+        let size = self.window.inner_size();
+        let extents = RafxExtents2D {
+            width: size.width,
+            height: size.height,
+        };
+        let sf = self.window.scale_factor();
+        let (damage, fit) = (&mut self.damage, &mut self.fit);
+        // both draw in pixels rather than skulpin's logical coordinates
+        self.renderer.lock().unwrap().draw(extents, sf, |canvas, _| match fit.as_mut() {
+            Some(fit) => fit.present(canvas, (size.width as f32, size.height as f32), sf as f32),
+            None => damage.repeat(canvas),
+        }).map_err(|e| format!("Error in draw routine {}", e))
+    }
+
+    pub fn snapshot(&mut self) -> Result<Image, String> {
+        self.render(true)?.ok_or_else(|| "Frame was not captured".to_string())
+    }
+
+    pub fn screenshot(&mut self, format:EncodedImageFormat, quality:u32) -> Result<Data, String> {
+        capture::encode(&self.snapshot()?, format, quality)
+    }
+
+    // Show a shared image as a picture-in-picture inset (or remove it by passing None). The
+    // upload to this window's context happens the next time it's drawn.
+    pub fn set_overlay(&mut self, shared:Option<Arc<SharedImage>>){
+        self.overlay = shared.map(|shared| (shared, None));
+        self.invalidate();
+    }
+
+    pub fn has_overlay(&self) -> bool {
+        self.overlay.is_some()
+    }
+
+    // Capture every frame from here on, writing them to files starting with `prefix`
+    pub fn start_recording(&mut self, format:RecordingFormat, prefix:&str){
+        self.recorder = Some(Rc::new(RefCell::new(Recorder::new(format, prefix))));
+    }
+
+    // The file gets written once the frames that are still being read back have arrived
+    pub fn stop_recording(&mut self){
+        self.recorder = None;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // Capture the next frame that gets rendered without stalling the pipeline. The pixels (shrunk
+    // by `scale` if it's less than 1.0) are delivered to the callback a couple of frames later.
+    pub fn read_pixels_async<F>(&mut self, scale:f32, callback:F)
+        where F:FnOnce(Result<Image, String>) + 'static
+    {
+        self.readback_requests.push((scale, Box::new(callback)));
+    }
+
+    // Save the last frame that was recorded as a picture, or record the next one and save that
+    pub fn save_picture(&mut self){
+        match self.pictures.last() {
+            Some(picture) if self.record_pictures => report_picture(picture::save(picture, "frame")),
+            _ => self.picture_requested = true,
+        }
+    }
+
+    // Draw the window's current frame again with Skia's SVG or PDF backend, at its logical size
+    // (or the size of its fixed canvas, if it has one)
+    pub fn export_frame(&mut self, format:ExportFormat) -> Data {
+        let size = self.canvas_size();
+        let (color, text) = (self.color, &mut self.text);
+        export::export(format, size, |canvas| {
+            draw_scene(canvas, &color, size, text.as_mut());
+        })
+    }
+
+    // The size of what the scene is drawn on, in the units it's laid out in: logical pixels for
+    // the window, or the canvas's own pixels if it has a fixed size
+    fn canvas_size(&self) -> (f32, f32) {
+        match self.fit.as_ref().map(CanvasFit::size) {
+            Some((width, height)) => (width as f32, height as f32),
+            None => {
+                let size = self.window.inner_size().to_logical::<f32>(self.window.scale_factor());
+                (size.width, size.height)
+            }
+        }
+    }
+
+    // draw & present a frame, optionally reading it back from skulpin's offscreen surface
+    // before it gets copied to the swapchain
+    fn render(&mut self, capture:bool) -> Result<Option<Image>, String>{
+        let _span = tracing::info_span!("render", window = self.id, frame = self.stats.lock().unwrap().frames).entered();
+        if let Some(recorder) = &self.recorder {
+            let recorder = Rc::clone(recorder);
+            self.read_pixels_async(1.0, move |frame| {
+                if let Err(msg) = frame.and_then(|frame| recorder.borrow_mut().add_frame(&frame)) {
+                    log::error!("Recording: {}", msg);
+                }
+            });
+        }
+
+        tracing::info_span!("readback_poll").in_scope(|| self.readback.poll());
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            let mut stats = self.stats.lock().unwrap();
+            for elapsed in timer.poll() {
+                stats.add_gpu_time(elapsed);
+            }
+        }
+        let started = Instant::now();
+        let prev = self.drawn;
 
         let window_size = self.window.inner_size();
         let window_extents = RafxExtents2D {
@@ -58,59 +551,361 @@ impl VulkanWindow {
             height: window_size.height,
         };
 
-        let mut s = 1.0/3.0 - 1.0/4.0 * ((self.color.h/180.0 * std::f32::consts::PI).cos() / 2.0 + 0.5);
-        let mut x = (self.color.h/180.0 * std::f32::consts::PI).sin() / 2.0 + 0.5;
-        let color:Color4f = self.color.to_color(255).into();
+        let color = self.color;
+        let hud = match self.show_hud {
+            true => Some((self.stats.lock().unwrap().clone(), self.id)),
+            false => None
+        };
+        let mut record = Duration::ZERO;
+        let text_block = &mut self.text;
+        let overlay = &mut self.overlay;
+        let budget = &mut self.budget;
+        let memory = &self.memory;
+        let purge = std::mem::take(&mut self.purge);
+        let abandon = std::mem::take(&mut self.abandon);
+        let mut abandoned = false;
+        let mut captured = None;
+        let readback = &mut self.readback;
+        let readback_requests = &mut self.readback_requests;
+        let gpu_timer = &mut self.gpu_timer;
+        let pictures = &mut self.pictures;
+        let record_picture = self.record_pictures || self.picture_requested;
+        let mut picture = None;
+        let damage_tracker = &mut self.damage;
+        let show_hud = self.show_hud;
+        let sf = self.window.scale_factor() as f32;
+        let (cw, ch) = self.canvas_size();
+        // the pixels the scene ends up in, and how far it's scaled up to fill them
+        let (pixels, scale) = match self.fit.as_ref().map(CanvasFit::size) {
+            Some(size) => (size, 1.0),
+            None => ((window_size.width as i32, window_size.height as i32), sf)
+        };
+        let fit = &mut self.fit;
+        let pointer = self.pointer;
 
-        if let Err(e) = self.renderer.lock().unwrap().draw(
+        // skulpin acquires the swapchain image, calls back to draw, then flushes and presents, all
+        // within `draw`, so only the callback can be given a span of its own
+        let renderer_span = tracing::info_span!("renderer_draw").entered();
+        let mut renderer = self.renderer.lock().unwrap();
+        let queue = renderer.graphics_queue.clone(); // ← what reads are copied with, behind Skia's work
+        let drawn = renderer.draw(
             window_extents,
             self.window.scale_factor(),
-            |canvas, coords| {
-                let cw = coords.window_logical_size().width as f32;
-                let ch = coords.window_logical_size().height as f32;
-                let w = 0.95 * cw;
-                let h = 0.95 * ch;
-
-                canvas.clear(Color4f::new(1.0, 1.0, 1.0, 1.0));
-
-                let rect = Rect::from_point_and_size(
-                    Point::new(
-                        (cw - w) / 2.0,
-                        (ch - h) / 2.0,
-                    ),
-                    (w,  h),
-                );
-                canvas.draw_rect(rect, &Paint::new(color, None));
-
-                x *= w;
-                s *= w;
-                let line = Rect::new(x-s/2.0, 0.0f32, x+s, ch);
-
-                let color:Color4f = Color::WHITE.into();
-                canvas.draw_rect(line, &Paint::new(color, None));
+            |canvas, _| {
+                let _span = tracing::info_span!("draw").entered();
+                if let Some(mut context) = canvas.direct_context() {
+                    if abandon {
+                        context.abandon();
+                    }
+                    abandoned = context.abandoned();
+                    if purge {
+                        context.purge_unlocked_resources(None, false);
+                    }
+                    budget.apply(&mut context);
+                    *memory.lock().unwrap() = MemoryUsage::of(&context);
+                }
+
+                // upload the shared image with the window's context (a picture's canvas doesn't have one)
+                if let Some((shared, image)) = overlay.as_mut() {
+                    if image.is_none() {
+                        *image = canvas.direct_context().and_then(|mut context| shared.image(&mut context));
+                    }
+                }
+
+                // pictures are recorded in logical coordinates, like everything else drawn here
+                if record_picture {
+                    pictures.begin((cw.ceil() as i32, ch.ceil() as i32));
+                }
+
+                // the damage is in the scene's coordinates too, and the retained frame is in pixels
+                let mut damage = scene_damage(&prev, &color, (cw, ch));
+                if show_hud {
+                    damage.add(stats::hud_bounds());
+                }
+                if pictures.is_recording() {
+                    damage_tracker.invalidate(); // ← pictures hold the whole frame, so it's drawn in full
+                }
+                if let Some(mut context) = canvas.direct_context() {
+                    damage_tracker.begin(&mut context, pixels, scale, &mut damage);
+                }
+
+                // a fixed-size canvas is drawn in its own pixels rather than skulpin's logical coordinates
+                let mut target = fit.as_mut()
+                    .and_then(|fit| canvas.direct_context().and_then(|mut context| fit.surface(&mut context)));
+
+                let window_canvas = canvas;
+                let canvas = match pictures.canvas() {
+                    Some(canvas) => canvas,
+                    None => match damage_tracker.canvas() {
+                        Some(canvas) => canvas,
+                        None => match target.as_mut() {
+                            Some(target) => target.canvas(),
+                            None => &mut *window_canvas,
+                        }
+                    }
+                };
+
+                draw_scene(canvas, &color, (cw, ch), text_block.as_mut());
+
+                if let Some((_, Some(image))) = overlay.as_ref() {
+                    shared::draw_picture_in_picture(canvas, image, (cw, ch));
+                }
+
+                if let Some(pointer) = pointer {
+                    fit::draw_pointer(canvas, pointer);
+                }
+
+                if let Some((stats, id)) = &hud {
+                    stats::draw_hud(canvas, stats, "Vulkan", *id);
+                }
+
+                let buffer_age = if target.is_some() { 1 } else { 0 };
+                let frame = match target.as_mut() {
+                    Some(target) => target.canvas(),
+                    None => &mut *window_canvas,
+                };
+                if let Some(recorded) = pictures.finish() {
+                    match damage_tracker.canvas() {
+                        Some(canvas) => canvas.draw_picture(&recorded, None, None),
+                        None => frame.draw_picture(&recorded, None, None),
+                    }
+                    picture = Some(recorded);
+                }
+
+                // VK_KHR_incremental_present is out of scope here: skulpin calls vkQueuePresentKHR
+                // itself, with no way to chain a VkPresentRegionsKHR onto it. The whole frame is
+                // presented, but only the damage was actually redrawn. Nothing says which frame
+                // skulpin's surface still holds, so only a fixed-size canvas (which always holds the
+                // last one) skips copying the whole frame.
+                damage_tracker.finish(frame, &damage, buffer_age);
+
+                // the canvas is scaled into the window (letterbox and all) afresh every frame
+                let canvas = window_canvas;
+                if let Some(fit) = fit.as_mut() {
+                    fit.present(canvas, (window_size.width as f32, window_size.height as f32), sf);
+                }
+
+                if capture {
+                    captured = Some(capture::read_canvas(canvas));
+                }
+
+                // skulpin draws into an offscreen surface that it later copies to the swapchain
+                let requests = readback_requests.drain(..);
+                match (unsafe { canvas.surface() }, canvas.direct_context()) {
+                    (Some(mut surface), Some(mut context)) => requests.for_each(|(scale, callback)| {
+                        readback.request(&mut surface, &mut context, &queue, scale, callback)
+                    }),
+                    _ => requests.for_each(|(_, callback)| {
+                        callback(Err("Canvas is not backed by a GPU surface".to_string()))
+                    }),
+                }
+                record = started.elapsed();
+
+                // Skia's work for the frame is submitted once this returns, so the GPU's timing
+                // starts here (anything already flushed above, like a readback, isn't counted)
+                if let Some(timer) = gpu_timer.as_mut() {
+                    timer.begin();
+                }
             },
-        ){
+        );
+        // ...and ends behind skulpin's copy of the frame into the swapchain
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            timer.end();
+        }
+        drop(renderer);
+        drop(renderer_span);
+
+        if let Err(e) = drawn {
             Err(format!("Error in draw routine {}", e))
+        }else if abandoned {
+            Err("Skia's context has been abandoned".to_string())
         }else{
-            Ok(())
+            // skulpin flushes and presents within `draw` so the two can't be timed separately (the
+            // GPU's time comes from gpu_timer.rs, a few frames later)
+            self.stats.lock().unwrap().add_frame(FrameTiming{
+                record,
+                flush: Duration::ZERO,
+                present: started.elapsed() - record,
+            });
+
+            if let Some(picture) = picture.filter(|_| std::mem::take(&mut self.picture_requested)) {
+                report_picture(picture::save(&picture, "frame"));
+            }
+
+            self.drawn = color;
+
+            // most of the wait for the first frame is building pipelines, which the cache cuts down on
+            if let Some(created) = self.created.take() {
+                log::info!("Context {} presented its first frame after {:.1} ms", self.id, created.elapsed().as_secs_f64() * 1000.0);
+                ready::first_frame_presented();
+            }
+            captured.transpose()
         }
     }
 }
 
 
+// The demo's content: a box in the window's color with a white bar sweeping across it, plus the
+// text panel if there is one. Skulpin's canvas is already in logical coordinates, so it's laid out
+// for a canvas `size` logical pixels across, and it only depends on `color` so `export_frame` can
+// draw the same frame again.
+fn draw_scene(canvas:&mut Canvas, color:&HSV, (cw, ch):(f32, f32), text:Option<&mut TextBlock>){
+    canvas.clear(Color4f::new(1.0, 1.0, 1.0, 1.0));
+
+    let fill:Color4f = color.to_color(255).into();
+    canvas.draw_rect(scene_box((cw, ch)), &Paint::new(fill, None));
+
+    let white:Color4f = Color::WHITE.into();
+    canvas.draw_rect(scene_bar(color, (cw, ch)), &Paint::new(white, None));
+
+    // the canvas is already in logical coordinates, so glyphs come out at the right scale
+    if let Some(block) = text {
+        text::draw_demo(canvas, block, (cw, ch));
+    }
+}
+
+fn scene_box((cw, ch):(f32, f32)) -> Rect {
+    let w = 0.95 * cw;
+    let h = 0.95 * ch;
+    Rect::from_point_and_size(
+        Point::new(
+            (cw - w) / 2.0,
+            (ch - h) / 2.0,
+        ),
+        (w,  h),
+    )
+}
+
+// the bar's position and width both follow the hue, which doubles as the animation's clock
+fn scene_bar(color:&HSV, (cw, ch):(f32, f32)) -> Rect {
+    let w = 0.95 * cw;
+    let s = (1.0/3.0 - 1.0/4.0 * ((color.h/180.0 * std::f32::consts::PI).cos() / 2.0 + 0.5)) * w;
+    let x = ((color.h/180.0 * std::f32::consts::PI).sin() / 2.0 + 0.5) * w;
+    Rect::new(x-s/2.0, 0.0f32, x+s, ch)
+}
+
+// What changes when the scene goes from `prev` to `color`: where the bar was and where it is now,
+// plus the box if its color is any different once it's been rounded to 8 bits
+fn scene_damage(prev:&HSV, color:&HSV, size:(f32, f32)) -> Damage {
+    let mut damage = Damage::default();
+    damage.add(scene_bar(prev, size));
+    damage.add(scene_bar(color, size));
+    if prev.to_color(255) != color.to_color(255) {
+        damage.add(scene_box(size));
+    }
+    damage
+}
+
+fn report_picture(saved:Result<(std::path::PathBuf, std::path::PathBuf), String>){
+    match saved {
+        Ok((skp, png)) => log::info!("Saved {} and {}", skp.display(), png.display()),
+        Err(msg) => log::error!("{}", msg),
+    }
+}
+
 fn main() {
-    // Setup logging
-    env_logger::Builder::from_default_env()
-        .filter_level(log::LevelFilter::Debug)
-        .init();
+    // VULKAN_VALIDATION turns on the Khronos validation layer and logs what it reports;
+    // VALIDATION_TEST also runs a short scripted session and fails if any errors were reported
+    let validation_test = std::env::var("VALIDATION_TEST").is_ok();
+    let validate = std::env::var("VULKAN_VALIDATION").is_ok() || validation_test;
+
+    // Setup logging (each window's messenger reports what the layer finds, so rafx's own
+    // reporter is quieted rather than logging it all a second time)
+    let mut logger = env_logger::Builder::from_default_env();
+    logger.filter_level(log::LevelFilter::Debug);
+    if validate {
+        logger.filter_module(validation::RAFX_REPORTER, log::LevelFilter::Off);
+    }
+    logger.init();
+
+    // GPU_OPTIONS adjusts how Skia sets up each window's context (see gpu_options.rs)
+    let gpu_options = env::parse::<GpuOptions>("GPU_OPTIONS").unwrap_or_default();
+    if gpu_options != GpuOptions::default() {
+        log::info!("GPU options: {}", gpu_options);
+    }
+
+    // VULKAN_ADAPTER picks the GPU every window renders with: an index from the list logged
+    // below, part of an adapter's name, or `low-power` / `high-performance`. Where that can't be
+    // done (see adapter::restrict_to) the demo exits rather than run on some other GPU.
+    let selector = env::parse::<AdapterSelector>("VULKAN_ADAPTER");
+    let mut selected = None;
+    match adapter::enumerate() {
+        Ok(adapters) => {
+            for adapter in &adapters {
+                log::info!("Vulkan adapter {}", adapter);
+            }
+            // without a selector rafx takes the adapter it rates highest, i.e., a discrete GPU first
+            if let Some(selector) = &selector {
+                match adapter::select(&adapters, selector) {
+                    Some(adapter) => match adapter::restrict_to(&adapters, adapter, selector) {
+                        Ok(()) => {
+                            log::info!("Using adapter {}", adapter);
+                            selected = Some((adapter.vendor_id, adapter.device_id));
+                        }
+                        Err(msg) => {
+                            log::error!("Could not select adapter {}: {}", adapter, msg);
+                            std::process::exit(1);
+                        }
+                    },
+                    None => {
+                        log::error!("No adapter matches VULKAN_ADAPTER={:?}", selector);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        Err(msg) => log::warn!("Could not list Vulkan adapters: {}", msg)
+    }
+
+    // SHADER_CACHE points the drivers' caches at a directory per Skia version, and has Skia cache
+    // its programs per driver beside them (see shader_cache.rs). Like the settings above, it's
+    // passed to the drivers through the environment, which has to happen before any threads are
+    // started (the tracing subscriber starts one).
+    let shader_cache = shader_cache::init("vulkan");
+
+    let validation = match validate {
+        true => match validation::enable() {
+            Ok(validation) => Some(validation),
+            Err(msg) => {
+                log::error!("Could not enable validation: {}", msg);
+                std::process::exit(1);
+            }
+        }
+        false => None
+    };
 
-    let event_loop = EventLoop::new();
+    let mut trace = trace::init();
+
+    let event_loop = EventLoop::with_user_event();
+
+    let present_mode = env::parse("PRESENT_MODE").unwrap_or(PresentMode::Fifo);
+
+    let record_format = env::parse("RECORD_FORMAT").unwrap_or(RecordingFormat::Sequence);
+
+    let export_format = env::parse("EXPORT_FORMAT").unwrap_or(ExportFormat::Svg);
+
+    // FONTS can list font files (separated like $PATH) to make available to every window
+    if let Some(paths) = std::env::var_os("FONTS") {
+        let paths:Vec<_> = std::env::split_paths(&paths).collect();
+        match text::use_fonts(&paths, None) {
+            Ok(families) => log::info!("Loaded fonts: {}", families.join(", ")),
+            Err(msg) => log::error!("Could not load fonts: {}", msg),
+        }
+    }
+
+    // GPU_BUDGET_MB caps the resource caches of all the windows combined
+    if let Some(megabytes) = env::parse::<usize>("GPU_BUDGET_MB") {
+        memory::set_budget(megabytes * 1024 * 1024);
+    }
 
     const WINDOW_COUNT: usize = 4;
     let size:LogicalSize<i32> = LogicalSize::new(400, 300);
     let mut loc:LogicalPosition<i32> = LogicalPosition::new(500, 300);
 
     let mut window_senders = HashMap::with_capacity(WINDOW_COUNT);
+    let mut window_stats = HashMap::with_capacity(WINDOW_COUNT);
+    let mut window_memory = HashMap::with_capacity(WINDOW_COUNT);
+    let mut window_threads = Vec::with_capacity(WINDOW_COUNT);
     for win_id in 0..WINDOW_COUNT {
 
         let os_window = WindowBuilder::new()
@@ -127,8 +922,18 @@ fn main() {
 
         let (tx, rx) = mpsc::channel();
         window_senders.insert(os_window.id(), tx);
+        let proxy:EventLoopProxy<UserEvent> = event_loop.create_proxy();
 
-        let mut window = VulkanWindow::new(os_window).unwrap();
+        let mut window = VulkanWindow::new(os_window, present_mode, &gpu_options, validation.as_ref()).unwrap();
+        log::info!("Created {:?} ({:?})", window.window.id(), window.present_mode());
+        if let Some((vendor_id, device_id, name)) = adapter::used_by(&window.renderer.lock().unwrap()) {
+            if selected.map_or(false, |ids| ids != (vendor_id, device_id)) {
+                log::error!("VULKAN_ADAPTER={:?} wasn't honored: the renderer is using {}", selector, name);
+                std::process::exit(1);
+            }
+        }
+        window_stats.insert(window.window.id(), window.stats());
+        window_memory.insert(window.window.id(), (window.id, window.memory()));
         window.color = match win_id {
             0 => HSV::from((0.0, 1.0, 0.2)),
             1 => HSV::from((90.0, 1.0, 0.5)),
@@ -136,8 +941,11 @@ fn main() {
             _ => HSV::from((270.0, 1.0, 1.0)),
         };
 
-        thread::spawn(move || {
-            while let Ok(event) = rx.recv() {
+        window_threads.push(thread::spawn(move || {
+            while let Ok(Dispatch{ event, span, sent }) = rx.recv() {
+                let _span = tracing::info_span!(parent: &span, "handle_event",
+                    window = window.id, queued_us = sent.elapsed().as_micros() as u64
+                ).entered();
                 match event {
 
                     Event::WindowEvent { event, .. } => match event {
@@ -164,20 +972,89 @@ fn main() {
                             window.resize(size);
 
                         },
+                        WindowEvent::CursorMoved { position, .. } => window.move_pointer(Some(position)),
+                        WindowEvent::CursorLeft { .. } => window.move_pointer(None),
+                        WindowEvent::KeyboardInput {
+                            input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(key), .. }, ..
+                        } => match key {
+                            VirtualKeyCode::H => window.toggle_hud(),
+                            VirtualKeyCode::T => window.toggle_text(),
+                            VirtualKeyCode::R => match window.is_recording() {
+                                true => window.stop_recording(),
+                                false => {
+                                    let prefix = format!("recording-{}", capture::timestamp());
+                                    window.start_recording(record_format, &prefix);
+                                    log::info!("Recording to {}…", prefix);
+                                }
+                            }
+                            VirtualKeyCode::S => {
+                                let format = EncodedImageFormat::PNG;
+                                let saved = window.screenshot(format, 100)
+                                    .and_then(|data| capture::save_timestamped(&data, "screenshot", capture::extension(format)));
+                                match saved {
+                                    Ok(path) => log::info!("Saved {}", path.display()),
+                                    Err(msg) => log::error!("Screenshot failed: {}", msg),
+                                }
+                            }
+                            VirtualKeyCode::E => {
+                                let data = window.export_frame(export_format);
+                                match capture::save_timestamped(&data, "frame", export_format.extension()) {
+                                    Ok(path) => log::info!("Exported {}", path.display()),
+                                    Err(msg) => log::error!("Export failed: {}", msg),
+                                }
+                            }
+                            VirtualKeyCode::F => window.cycle_fit(),
+                            VirtualKeyCode::L => window.abandon_context(),
+                            VirtualKeyCode::D => window.save_picture(),
+                            // the main thread hands the snapshot to every window (this one included)
+                            VirtualKeyCode::P => match window.has_overlay() {
+                                true => { proxy.send_event(UserEvent::Overlay(None)).ok(); }
+                                false => match window.snapshot().and_then(|image| SharedImage::new(&image)) {
+                                    Ok(shared) => { proxy.send_event(UserEvent::Overlay(Some(Arc::new(shared)))).ok(); }
+                                    Err(msg) => log::error!("Could not share snapshot: {}", msg),
+                                }
+                            }
+                            _ => {}
+                        },
                         _ => {}
                     }
-                    Event::RedrawRequested(_) => {
-                        window.redraw().unwrap();
+                    Event::UserEvent(UserEvent::Overlay(shared)) => {
+                        window.set_overlay(shared);
+                    }
+                    Event::UserEvent(UserEvent::Resize(size)) => {
+                        window.window.set_inner_size(size);
+                    }
+                    // a failed frame usually means the device was lost; rebuild it and carry on
+                    Event::RedrawRequested(_) | Event::UserEvent(UserEvent::Tick) => {
+                        let drawn = match matches!(event, Event::UserEvent(UserEvent::Tick)) {
+                            true => window.update(),
+                            false => window.redraw(), // ← the OS asked, so the last frame is shown again
+                        };
+                        if let Err(msg) = drawn {
+                            log::error!("{}", msg);
+                            match window.restore() {
+                                Ok(()) => log::info!("Restored context {}", window.id),
+                                Err(msg) => {
+                                    log::error!("Could not restore context {}: {}", window.id, msg);
+                                    break
+                                }
+                            }
+                        }
                     },
                     _ => {}
                 }
             }
-        });
+        }));
     }
 
     let frame_time = Duration::from_micros(1_000_000 / 60);
     let mut next_frame = Instant::now() + frame_time;
 
+    // the validation test grows, shrinks, and then closes the windows, a step per second
+    let test_step_time = Duration::from_secs(1);
+    let mut next_test_step = Instant::now() + test_step_time;
+    let mut test_step = 0;
+
     // Start the window event loop. Winit will not return once run is called. We will get notified
     // when important events happen.
     event_loop.run(move |event, _window_target, control_flow| {
@@ -191,37 +1068,100 @@ fn main() {
                 WindowEvent::CloseRequested |
                 WindowEvent::Destroyed => {
                     window_senders.remove(&window_id);
+                    window_stats.remove(&window_id);
+                    window_memory.remove(&window_id);
                     if window_senders.is_empty(){
                         *control_flow = ControlFlow::Exit;
                     }
                 }
+                WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::M), .. }, .. } => {
+                    let report = MemoryReport::new(window_memory.values().map(|(id, usage)|
+                        (*id, *usage.lock().unwrap())
+                    ).collect());
+                    log::info!("GPU memory:\n{}", report);
+                }
                 _ => {
                     if let Some(tx) = window_senders.get(&window_id) {
                         if let Some(event) = event.to_static() {
-                            tx.send(event).unwrap();
+                            tx.send(Dispatch::new(event)).ok();
                         }
                     }
                 }
             },
+            Event::LoopDestroyed => {
+                // hang up on the render threads and let them drop their windows (finishing any
+                // recordings in progress) before the process exits
+                window_senders.clear();
+                for handle in window_threads.drain(..) {
+                    handle.join().ok();
+                }
+                trace.take(); // ← writes out the trace file
+                if let Some(cache) = &shader_cache {
+                    log::info!("Shader cache: {}", cache.report());
+                }
+
+                if let Some(validation) = &validation {
+                    log::info!("Validation: {} errors, {} warnings", validation.errors(), validation.warnings());
+                    if validation_test && validation.errors() > 0 {
+                        std::process::exit(1);
+                    }
+                }
+            }
+            Event::UserEvent(UserEvent::Overlay(shared)) => {
+                for tx in window_senders.values() {
+                    tx.send(Dispatch::new(Event::UserEvent(UserEvent::Overlay(shared.clone())))).ok();
+                }
+            }
             Event::RedrawRequested(window_id) => {
                 if let Some(tx) = window_senders.get(&window_id) {
                     if let Some(event) = event.to_static() {
-                        tx.send(event).unwrap();
+                        tx.send(Dispatch::new(event)).ok();
                     }
                 }
             }
 
             Event::MainEventsCleared => {
-                // Queue a RedrawRequested event.
+                if validation_test && Instant::now() > next_test_step {
+                    next_test_step += test_step_time;
+                    test_step += 1;
+                    let resize = |size:LogicalSize<u32>| for tx in window_senders.values() {
+                        tx.send(Dispatch::new(Event::UserEvent(UserEvent::Resize(size)))).ok();
+                    };
+                    match test_step {
+                        1 => resize(LogicalSize::new(640, 480)),
+                        2 => resize(LogicalSize::new(200, 150)),
+                        3 => {
+                            // close half the windows while the rest keep drawing
+                            let closing:Vec<_> = window_senders.keys().take(window_senders.len() / 2).cloned().collect();
+                            for window_id in closing {
+                                window_senders.remove(&window_id);
+                                window_stats.remove(&window_id);
+                                window_memory.remove(&window_id);
+                            }
+                        }
+                        _ => {
+                            window_senders.clear();
+                            window_stats.clear();
+                            window_memory.clear();
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
+                }
+
+                // Tell every window it's time for the next frame
                 let now = Instant::now();
                 if now > next_frame{
+                    let mut missed = 0;
                     while next_frame < now {
                         next_frame += frame_time;
+                        missed += 1;
                     }
-                    for (win_id, tx) in window_senders.iter() {
-                        if let Some(event) = Event::RedrawRequested(*win_id).to_static() {
-                            tx.send(event).unwrap();
-                        }
+                    for (_, stats) in window_stats.iter() {
+                        stats.lock().unwrap().dropped += missed.max(1) - 1;
+                    }
+                    let _span = tracing::info_span!("tick", windows = window_senders.len(), missed).entered();
+                    for tx in window_senders.values() {
+                        tx.send(Dispatch::new(Event::UserEvent(UserEvent::Tick))).ok();
                     }
                 }
             }
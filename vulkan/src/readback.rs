@@ -0,0 +1,232 @@
+use std::collections::VecDeque;
+use ash::vk::{self, Handle};
+use ash::version::{DeviceV1_0, InstanceV1_0};
+use skia_safe::{
+    AlphaType, ColorType, Data, FilterMode, Image, ImageInfo, MipmapMode, Paint, Rect,
+    SamplingOptions, Surface, gpu::{self, BackendSurfaceMutableState, BackendTexture, DirectContext, SurfaceOrigin}
+};
+use skulpin::rafx::api::{
+    RafxCommandBuffer, RafxCommandBufferDef, RafxCommandPool, RafxCommandPoolDef, RafxDeviceContext,
+    RafxExtents3D, RafxFence, RafxFenceStatus, RafxFormat, RafxQueue, RafxResourceType, RafxTexture,
+    RafxTextureDef
+};
+
+pub type ReadbackCallback = Box<dyn FnOnce(Result<Image, String>)>;
+
+// A copy of a frame on its way to the CPU. Everything the GPU touches is kept alive until the
+// fence says it's done.
+struct InFlight {
+    _commands: RafxCommandBuffer,
+    _pool: RafxCommandPool,
+    _texture: RafxTexture, // ← the copy of the frame the transfer reads from
+    buffer: HostBuffer,
+    fence: RafxFence,
+    info: ImageInfo,
+}
+
+struct PendingRead {
+    copy: InFlight,
+    callback: ReadbackCallback,
+}
+
+// Non-blocking framebuffer reads, the Vulkan counterpart of Metal's. The frame is drawn (shrunk by
+// `scale` first if it's less than 1.0) into a texture of our own, created on rafx's device, which
+// Skia leaves ready to be copied from. A command buffer of our own then copies it into host memory,
+// submitted to the same queue right behind Skia's work with a fence to say when it's done. That's
+// usually a frame or two later, and `poll` hands the results off in order, so the CPU never waits
+// on the GPU.
+#[derive(Default)]
+pub struct DeferredReadback {
+    pending: VecDeque<PendingRead>,
+}
+
+impl DeferredReadback {
+    // Has to be called before the frame is flushed, with the context its surface belongs to and
+    // the queue the context submits to
+    pub fn request(&mut self, surface:&mut Surface, context:&mut DirectContext, queue:&RafxQueue, scale:f32, callback:ReadbackCallback){
+        match copy(surface, context, queue, scale) {
+            Ok(copy) => self.pending.push_back(PendingRead{ copy, callback }),
+            Err(msg) => callback(Err(msg)),
+        }
+    }
+
+    // Hand off every read whose copy the GPU has finished, leaving the rest for later
+    pub fn poll(&mut self){
+        while let Some(read) = self.pending.front() {
+            let result = match read.copy.fence.get_fence_status() {
+                Ok(RafxFenceStatus::Complete) => read.copy.buffer.image(&read.copy.info),
+                Ok(_) => break, // ← the copies were submitted in order, so the rest aren't done either
+                Err(e) => Err(format!("The GPU could not copy the frame: {}", e)),
+            };
+            let read = self.pending.pop_front().unwrap();
+            (read.callback)(result);
+        }
+    }
+
+    // Fail every read that's still waiting, e.g., because the context the copies were made with
+    // has been lost. This also has to happen before the device they were made on is destroyed.
+    pub fn abandon(&mut self){
+        for read in self.pending.drain(..) {
+            // a copy still in flight has to finish before its memory is freed (a lost device
+            // returns right away)
+            read.copy.buffer.device_context.wait_for_fences(&[&read.copy.fence]).ok();
+            (read.callback)(Err("Context was lost before the read completed".to_string()));
+        }
+    }
+}
+
+fn copy(surface:&mut Surface, context:&mut DirectContext, queue:&RafxQueue, scale:f32) -> Result<InFlight, String> {
+    let device_context = queue.device_context();
+    let vk_context = device_context.vk_device_context().ok_or("Not a Vulkan device")?;
+    let queue_family = vk_context.queue_family_indices().graphics_queue_family_index;
+
+    let frame = surface.image_snapshot();
+    let (width, height) = match scale < 1.0 {
+        true => (((frame.width() as f32 * scale) as i32).max(1), ((frame.height() as f32 * scale) as i32).max(1)),
+        false => (frame.width(), frame.height()),
+    };
+
+    // draw the frame into a texture that can be copied from (unlike skulpin's surface), shrinking
+    // it along the way so only the reduced pixels need to be transferred. rafx gives textures that
+    // can be sampled transfer usage too.
+    let texture = device_context.create_texture(&RafxTextureDef{
+        extents: RafxExtents3D{ width: width as u32, height: height as u32, depth: 1 },
+        format: RafxFormat::R8G8B8A8_UNORM,
+        resource_type: RafxResourceType::TEXTURE | RafxResourceType::RENDER_TARGET_COLOR,
+        ..Default::default()
+    }).map_err(|e| format!("Could not create texture for readback: {}", e))?;
+    let image = texture.vk_texture().ok_or("Not a Vulkan texture")?.vk_image();
+
+    let backend_texture = unsafe {
+        let mut info = gpu::vk::ImageInfo::new(
+            image.as_raw() as _, gpu::vk::Alloc::default(), gpu::vk::ImageTiling::OPTIMAL,
+            gpu::vk::ImageLayout::UNDEFINED, gpu::vk::Format::R8G8B8A8_UNORM, 1, queue_family,
+            None, None, None
+        );
+        info.image_usage_flags = (vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::COLOR_ATTACHMENT
+            | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST).as_raw();
+        BackendTexture::new_vulkan((width, height), &info)
+    };
+    let mut target = Surface::from_backend_texture(
+        context, &backend_texture, SurfaceOrigin::TopLeft, None, ColorType::RGBA8888, None, None
+    ).ok_or_else(|| "Could not wrap texture for readback".to_string())?;
+    target.canvas().draw_image_rect_with_sampling_options(
+        &frame, None, Rect::from_iwh(width, height),
+        SamplingOptions::new(FilterMode::Linear, MipmapMode::None),
+        &Paint::default()
+    );
+    drop(target);
+
+    // Skia tracks the texture's layout, so it's asked to move it to the one the copy needs, which
+    // it does at the end of the work it submits here (ahead of the copy)
+    let state = BackendSurfaceMutableState::new_vk(gpu::vk::ImageLayout::TRANSFER_SRC_OPTIMAL, queue_family);
+    if !context.set_backend_texture_state(&backend_texture, &state) {
+        return Err("Could not prepare the texture to be read back".to_string())
+    }
+    context.flush_and_submit();
+
+    let info = ImageInfo::new((width, height), ColorType::RGBA8888, AlphaType::Premul, None);
+    let buffer = HostBuffer::new(&device_context, info.min_row_bytes() * height as usize)?;
+
+    let mut pool = queue.create_command_pool(&RafxCommandPoolDef{ transient: true })
+        .map_err(|e| format!("Could not create command pool: {}", e))?;
+    let commands = pool.create_command_buffer(&RafxCommandBufferDef{ is_secondary: false })
+        .map_err(|e| format!("Could not create command buffer: {}", e))?;
+    commands.begin().map_err(|e| format!("Could not begin command buffer: {}", e))?;
+    unsafe {
+        // rafx has no command for copying a texture into a buffer, so it's recorded with Vulkan's
+        let device = vk_context.device();
+        let vk_commands = commands.vk_command_buffer().ok_or("Not a Vulkan command buffer")?.vk_command_buffer();
+        let region = vk::BufferImageCopy::builder()
+            .image_subresource(vk::ImageSubresourceLayers{
+                aspect_mask: vk::ImageAspectFlags::COLOR, mip_level: 0, base_array_layer: 0, layer_count: 1
+            })
+            .image_extent(vk::Extent3D{ width: width as u32, height: height as u32, depth: 1 });
+        device.cmd_copy_image_to_buffer(vk_commands, image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, buffer.buffer, &[region.build()]);
+
+        // make what was copied visible to the CPU once the fence has signaled
+        let barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(buffer.buffer)
+            .size(vk::WHOLE_SIZE);
+        device.cmd_pipeline_barrier(
+            vk_commands, vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(), &[], &[barrier.build()], &[]
+        );
+    }
+    commands.end().map_err(|e| format!("Could not end command buffer: {}", e))?;
+
+    let fence = device_context.create_fence().map_err(|e| format!("Could not create fence: {}", e))?;
+    queue.submit(&[&commands], &[], &[], Some(&fence)).map_err(|e| format!("Could not submit copy: {}", e))?;
+
+    Ok(InFlight{ _commands: commands, _pool: pool, _texture: texture, buffer, fence, info })
+}
+
+// Host-visible memory a frame is copied into. rafx's buffers that the CPU reads from can't be
+// copied into, so this one's made with Vulkan directly, on rafx's device.
+struct HostBuffer {
+    device_context: RafxDeviceContext,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    pixels: *const u8,
+}
+
+impl HostBuffer {
+    fn new(device_context:&RafxDeviceContext, size:usize) -> Result<Self, String> {
+        let vk_context = device_context.vk_device_context().ok_or("Not a Vulkan device")?;
+        let device = vk_context.device();
+        unsafe {
+            let info = vk::BufferCreateInfo::builder()
+                .size(size as u64)
+                .usage(vk::BufferUsageFlags::TRANSFER_DST)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            let buffer = device.create_buffer(&info, None).map_err(|e| format!("Could not create readback buffer: {}", e))?;
+
+            // from here on, dropping it cleans up whatever has been created so far
+            let mut host = HostBuffer{
+                device_context: device_context.clone(), buffer, memory: vk::DeviceMemory::null(), pixels: std::ptr::null()
+            };
+
+            // any memory the CPU can see will do, but cached memory is much quicker to read
+            let requirements = device.get_buffer_memory_requirements(buffer);
+            let properties = vk_context.instance().get_physical_device_memory_properties(vk_context.physical_device());
+            let usable = |flags:vk::MemoryPropertyFlags| (0..properties.memory_type_count).find(|&i|
+                requirements.memory_type_bits & (1 << i) != 0 && properties.memory_types[i as usize].property_flags.contains(flags)
+            );
+            let coherent = vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT;
+            let memory_type = usable(coherent | vk::MemoryPropertyFlags::HOST_CACHED).or_else(|| usable(coherent))
+                .ok_or("No memory the CPU can read frames from")?;
+
+            let allocate = vk::MemoryAllocateInfo::builder()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type);
+            host.memory = device.allocate_memory(&allocate, None).map_err(|e| format!("Could not allocate readback memory: {}", e))?;
+            device.bind_buffer_memory(buffer, host.memory, 0).map_err(|e| format!("Could not bind readback memory: {}", e))?;
+            host.pixels = device.map_memory(host.memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                .map_err(|e| format!("Could not map readback memory: {}", e))? as *const u8;
+            Ok(host)
+        }
+    }
+
+    fn image(&self, info:&ImageInfo) -> Result<Image, String> {
+        let len = info.min_row_bytes() * info.height() as usize;
+        let pixels = unsafe { std::slice::from_raw_parts(self.pixels, len) };
+        Image::from_raster_data(info, Data::new_copy(pixels), info.min_row_bytes())
+            .ok_or_else(|| "Could not create image from snapshot".to_string())
+    }
+}
+
+impl Drop for HostBuffer {
+    fn drop(&mut self){
+        if let Some(vk_context) = self.device_context.vk_device_context() {
+            unsafe {
+                let device = vk_context.device();
+                device.destroy_buffer(self.buffer, None);
+                device.free_memory(self.memory, None); // ← unmaps it too
+            }
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use skia_safe::{
+    Canvas, Color, Image, Paint, PaintStyle, Rect, SamplingOptions, FilterMode, MipmapMode,
+    image::CachingHint, gpu::{DirectContext, Mipmapped}
+};
+
+// An image that can be drawn in any window. skulpin gives every window its own Vulkan instance
+// and device, so there's no device memory that two windows' contexts could both reference.
+// Instead a SharedImage keeps a single raster copy of the pixels (which can be passed between
+// render threads) and each window uploads it to its own context the first time it's drawn,
+// reusing that texture from then on.
+pub struct SharedImage {
+    pixels: Image,
+}
+
+impl SharedImage {
+    pub fn new(image:&Image) -> Result<Self, String> {
+        image.to_raster_image(CachingHint::Disallow)
+            .map(|pixels| SharedImage{ pixels })
+            .ok_or_else(|| "Could not read pixels from image".to_string())
+    }
+
+    // Upload the pixels for drawing with a particular window's DirectContext
+    pub fn image(&self, context:&mut DirectContext) -> Option<Image> {
+        self.pixels.new_texture_image(context, Mipmapped::No)
+    }
+}
+
+// Draw an image as an inset along the top-right edge of a window (in logical pixels), scaled
+// to a quarter of its width
+pub fn draw_picture_in_picture(canvas:&mut Canvas, image:&Image, (window_width, _):(f32, f32)){
+    let inset = 12.0;
+    let width = window_width / 4.0;
+    let height = width * image.height() as f32 / image.width().max(1) as f32;
+    let frame = Rect::from_xywh(window_width - width - inset, inset, width, height);
+
+    canvas.draw_image_rect_with_sampling_options(
+        image, None, frame, SamplingOptions::new(FilterMode::Linear, MipmapMode::None), &Paint::default()
+    );
+
+    let mut border = Paint::new(Color::BLACK.into(), None);
+    border.set_style(PaintStyle::Stroke);
+    border.set_stroke_width(2.0);
+    canvas.draw_rect(frame, &border);
+}
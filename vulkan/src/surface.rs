@@ -0,0 +1,52 @@
+use ash::vk;
+use ash::extensions::{khr, mvk};
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
+use skulpin::Renderer;
+use winit::window::Window;
+
+// The present modes a window's surface supports on the device its renderer runs on. rafx keeps
+// the mode its swapchain picked to itself (it only logs it), so this asks the driver the same
+// question rafx did: on rafx's own instance & physical device, with a second surface for the same
+// window that's destroyed again right away.
+pub fn present_modes(window:&Window, renderer:&Renderer) -> Result<Vec<vk::PresentModeKHR>, String> {
+    let handle = window.raw_window_handle();
+    let device_context = renderer.api.device_context();
+    let device_context = device_context.vk_device_context().ok_or("Not a Vulkan renderer")?;
+    let (instance, physical_device) = (device_context.instance(), device_context.physical_device());
+
+    unsafe {
+        // rafx's entry isn't exposed either, but loading the library again yields the same loader
+        let entry = ash::Entry::new().map_err(|e| format!("Could not load Vulkan: {}", e))?;
+        let surface = match handle {
+            RawWindowHandle::AppKit(handle) => {
+                let info = vk::MacOSSurfaceCreateInfoMVK::builder().view(&*handle.ns_view);
+                mvk::MacOSSurface::new(&entry, instance).create_mac_os_surface_mvk(&info, None)
+            }
+            RawWindowHandle::Win32(handle) => {
+                let info = vk::Win32SurfaceCreateInfoKHR::builder().hinstance(handle.hinstance).hwnd(handle.hwnd);
+                khr::Win32Surface::new(&entry, instance).create_win32_surface(&info, None)
+            }
+            RawWindowHandle::Xlib(handle) => {
+                let info = vk::XlibSurfaceCreateInfoKHR::builder().dpy(handle.display as *mut vk::Display).window(handle.window);
+                khr::XlibSurface::new(&entry, instance).create_xlib_surface(&info, None)
+            }
+            RawWindowHandle::Xcb(handle) => {
+                let info = vk::XcbSurfaceCreateInfoKHR::builder().connection(handle.connection as *mut _).window(handle.window);
+                khr::XcbSurface::new(&entry, instance).create_xcb_surface(&info, None)
+            }
+            RawWindowHandle::Wayland(handle) => {
+                let info = vk::WaylandSurfaceCreateInfoKHR::builder().display(handle.display as *mut _).surface(handle.surface as *mut _);
+                khr::WaylandSurface::new(&entry, instance).create_wayland_surface(&info, None)
+            }
+            _ => return Err("Unsupported window handle".to_string())
+        };
+
+        let surfaces = khr::Surface::new(&entry, instance);
+        surface.map_err(|e| format!("Could not create surface: {}", e)).and_then(|surface| {
+            let modes = surfaces.get_physical_device_surface_present_modes(physical_device, surface)
+                .map_err(|e| format!("Could not get present modes: {}", e));
+            surfaces.destroy_surface(surface, None);
+            modes
+        })
+    }
+}
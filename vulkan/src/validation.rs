@@ -0,0 +1,127 @@
+use std::borrow::Cow;
+use std::ffi::{c_void, CStr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use ash::vk;
+use ash::extensions::ext::DebugUtils;
+use ash::version::EntryV1_0;
+use log::Level;
+use skulpin::Renderer;
+
+pub const LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+// rafx registers a messenger of its own that logs everything under this target. Once the windows
+// have theirs, it only repeats what they report.
+pub const RAFX_REPORTER: &str = "rafx_api::backends::vulkan::internal::debug_reporter";
+
+// What the layer has reported, counted across every window
+#[derive(Clone, Default)]
+pub struct Validation {
+    errors: Arc<AtomicUsize>,
+    warnings: Arc<AtomicUsize>,
+}
+
+// rafx decides whether each window's instance gets the validation layer: in debug builds it turns
+// on the layer along with VK_EXT_debug_utils (which the messengers need), as long as both are
+// installed. This checks that they will be, and has to be called before any renderer is built.
+pub fn enable() -> Result<Validation, String> {
+    if !cfg!(debug_assertions) {
+        return Err("rafx only turns on the validation layer in debug builds".to_string())
+    }
+
+    let entry = unsafe { ash::Entry::new() }.map_err(|e| format!("Could not load Vulkan: {}", e))?;
+    let layers = entry.enumerate_instance_layer_properties()
+        .map_err(|e| format!("Could not list Vulkan layers: {}", e))?;
+    let available = layers.iter().any(|layer|
+        unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) }.to_str() == Ok(LAYER)
+    );
+    if !available {
+        return Err(format!("{} is not installed (it comes with the Vulkan SDK)", LAYER))
+    }
+
+    let extensions = entry.enumerate_instance_extension_properties()
+        .map_err(|e| format!("Could not list Vulkan extensions: {}", e))?;
+    let debug_utils = extensions.iter().any(|extension|
+        unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) } == DebugUtils::name()
+    );
+    if !debug_utils {
+        return Err(format!("{} is not available", DebugUtils::name().to_string_lossy()))
+    }
+
+    Ok(Validation::default())
+}
+
+// A window's VK_EXT_debug_utils messenger, passing what the layer reports about its instance and
+// device along to `log`. It has to be dropped before the renderer it was registered with.
+pub struct Messenger {
+    loader: DebugUtils,
+    messenger: vk::DebugUtilsMessengerEXT,
+    _reporter: Box<Reporter>, // ← what the callback is handed, for as long as it can be called
+}
+
+struct Reporter {
+    window: usize,
+    validation: Validation,
+}
+
+impl Validation {
+    pub fn errors(&self) -> usize {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.warnings.load(Ordering::Relaxed)
+    }
+
+    // Register a messenger on the instance rafx created for `renderer`, tagging what it reports
+    // with the window's id. Anything reported while the instance was being created (or after the
+    // messenger is gone) only reaches rafx's own messenger.
+    pub fn attach(&self, renderer:&Renderer, window:usize) -> Result<Messenger, String> {
+        let device_context = renderer.api.device_context();
+        let vk_context = device_context.vk_device_context().ok_or("Not a Vulkan renderer")?;
+        let loader = DebugUtils::new(vk_context.entry(), vk_context.instance());
+        let reporter = Box::new(Reporter{ window, validation: self.clone() });
+
+        let create_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+            .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO)
+            .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
+            .pfn_user_callback(Some(report))
+            .user_data(&*reporter as *const Reporter as *mut c_void);
+        let messenger = unsafe { loader.create_debug_utils_messenger(&create_info, None) }
+            .map_err(|e| format!("Could not create debug messenger: {}", e))?;
+        Ok(Messenger{ loader, messenger, _reporter: reporter })
+    }
+}
+
+impl Drop for Messenger {
+    fn drop(&mut self){
+        unsafe { self.loader.destroy_debug_utils_messenger(self.messenger, None) }
+    }
+}
+
+unsafe extern "system" fn report(
+    severity:vk::DebugUtilsMessageSeverityFlagsEXT,
+    kind:vk::DebugUtilsMessageTypeFlagsEXT,
+    data:*const vk::DebugUtilsMessengerCallbackDataEXT,
+    reporter:*mut c_void,
+) -> vk::Bool32 {
+    let reporter = &*(reporter as *const Reporter);
+    let message = match data.as_ref() {
+        Some(data) if !data.p_message.is_null() => CStr::from_ptr(data.p_message).to_string_lossy(),
+        _ => Cow::Borrowed("(no message)"),
+    };
+
+    let level = if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        reporter.validation.errors.fetch_add(1, Ordering::Relaxed);
+        Level::Error
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        reporter.validation.warnings.fetch_add(1, Ordering::Relaxed);
+        Level::Warn
+    } else {
+        Level::Info
+    };
+    log::log!(target: "validation", level, "window {} {:?}: {}", reporter.window, kind, message);
+    vk::FALSE // ← don't abort the call that set it off
+}